@@ -0,0 +1,139 @@
+//! Benchmarks turning a status response into a `SpotifyStatus`.
+//!
+//! Run with `cargo bench --bench status_parse`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use json::JsonValue;
+use spotify::status::{SpotifyStatus, Track};
+
+/// A typical status response.
+const STATUS: &str = r#"{
+    "version": 9,
+    "client_version": "1.0.42.151.g19de0aa6",
+    "playing": true,
+    "shuffle": false,
+    "repeat": false,
+    "play_enabled": true,
+    "prev_enabled": true,
+    "next_enabled": true,
+    "track": {
+        "track_resource": {
+            "name": "Never Gonna Give You Up",
+            "uri": "spotify:track:4uLU6hMCjMI75M1A2tKUQC",
+            "location": {"og": "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC"}
+        },
+        "artist_resource": {
+            "name": "Rick Astley",
+            "uri": "spotify:artist:0gxyHStUsqpMadRV0Di1Qt",
+            "location": {"og": "https://open.spotify.com/artist/0gxyHStUsqpMadRV0Di1Qt"}
+        },
+        "album_resource": {
+            "name": "Whenever You Need Somebody",
+            "uri": "spotify:album:6N9PS4QXF1D0OWPk0Sxtb4",
+            "location": {"og": "https://open.spotify.com/album/6N9PS4QXF1D0OWPk0Sxtb4"}
+        },
+        "length": 213,
+        "track_type": "normal"
+    },
+    "context": {},
+    "playing_position": 42.5,
+    "server_time": 1500000000,
+    "volume": 0.75,
+    "online": true,
+    "open_graph_state": {"private_session": false, "posting_disabled": true},
+    "running": true
+}"#;
+
+// The latest representable server time (9999-12-31T23:59:59Z)
+const MAX_SERVER_TIME: i64 = 253_402_300_799;
+
+/// A status parsed the way `SpotifyStatus::from` used to,
+/// with the same fields as `SpotifyStatus`.
+#[allow(dead_code)]
+struct BaselineStatus {
+    volume: f32,
+    online: bool,
+    version: i32,
+    running: bool,
+    playing: bool,
+    shuffle: bool,
+    repeat: bool,
+    server_time: i64,
+    play_enabled: bool,
+    prev_enabled: bool,
+    next_enabled: bool,
+    client_version: String,
+    playing_position: f32,
+    private_session: bool,
+    posting_disabled: bool,
+    track: Track,
+    context_uri: Option<String>,
+}
+
+/// Transforms a JSON value into a finite f32,
+/// parsing numbers given as strings as well.
+fn get_json_f32(json: &JsonValue) -> f32 {
+    json.as_f32()
+        .or_else(|| json.as_str()?.trim().parse().ok())
+        .filter(|val| val.is_finite())
+        .unwrap_or(0_f32)
+}
+
+/// Parses the volume into the range [0.0...1.0].
+fn get_json_volume(json: &JsonValue) -> f32 {
+    let volume = get_json_f32(json);
+    if volume > 1_f32 {
+        (volume / 100_f32).min(1_f32)
+    } else {
+        volume.max(0_f32)
+    }
+}
+
+/// Parses the status looking up each field separately,
+/// the way `SpotifyStatus::from` did before parsing in a single pass.
+fn baseline_from(json: JsonValue) -> BaselineStatus {
+    BaselineStatus {
+        volume: get_json_volume(&json["volume"]),
+        online: json["online"] == true,
+        version: json["version"].as_i32().unwrap_or(0_i32),
+        running: json["running"] == true,
+        playing: json["playing"] == true,
+        shuffle: json["shuffle"] == true,
+        repeat: json["repeat"] == true,
+        server_time: json["server_time"]
+            .as_i64()
+            .filter(|time| (0..=MAX_SERVER_TIME).contains(time))
+            .unwrap_or(0_i64),
+        play_enabled: json["play_enabled"] == true,
+        prev_enabled: json["prev_enabled"] == true,
+        next_enabled: json["next_enabled"] == true,
+        client_version: json["client_version"].as_str().unwrap_or("").to_owned(),
+        playing_position: get_json_f32(&json["playing_position"]),
+        private_session: json["open_graph_state"]["private_session"] == true,
+        posting_disabled: json["open_graph_state"]["posting_disabled"] == true,
+        track: Track::from(&json["track"]),
+        context_uri: json["context"]["uri"]
+            .as_str()
+            .filter(|uri| !uri.is_empty())
+            .map(str::to_owned),
+    }
+}
+
+fn status_parse(c: &mut Criterion) {
+    let json = json::parse(STATUS).unwrap();
+    c.bench_function("baseline_from_json", |b| {
+        b.iter(|| baseline_from(black_box(json.clone())))
+    });
+    c.bench_function("status_from_json", |b| {
+        b.iter(|| SpotifyStatus::from(black_box(json.clone())))
+    });
+    c.bench_function("baseline_parse_and_from", |b| {
+        b.iter(|| baseline_from(json::parse(black_box(STATUS)).unwrap()))
+    });
+    c.bench_function("status_parse_and_from", |b| {
+        b.iter(|| SpotifyStatus::from(json::parse(black_box(STATUS)).unwrap()))
+    });
+}
+
+criterion_group!(benches, status_parse);
+criterion_main!(benches);
@@ -1,25 +1,70 @@
 extern crate spotify;
-use spotify::{Spotify, SpotifyError};
+use json::JsonValue;
+use spotify::{DesiredState, Spotify, SpotifyError};
+use std::time::Duration;
+
+const USAGE: &str = "Usage: spoticli [--json] [--once]
+       spoticli play <uri> | pause | resume | next | prev | volume <0-100>
+
+Commands the client does not support exit with code 8.";
+
+/// A command to issue instead of printing the status.
+enum Command {
+    Play(String),
+    Pause,
+    Resume,
+    Next,
+    Prev,
+    Volume(f32),
+}
 
 fn main() {
+    // Print each status update as a JSON line instead of text.
+    let mut json = false;
+    // Print the current status once instead of polling.
+    let mut once = false;
+    let mut args = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--once" => once = true,
+            flag if flag.starts_with("--") => exit_with_usage(),
+            _ => args.push(arg),
+        }
+    }
+    let command = parse_command(&args);
     let spotify = match Spotify::connect() {
         Ok(result) => result,
-        Err(error) => match error {
-            SpotifyError::ClientNotRunning => {
-                println!("The Spotify Client is not running!");
-                std::process::exit(1);
-            }
-            SpotifyError::WebHelperNotRunning => {
-                println!("The SpotifyWebHelper process is not running!");
-                std::process::exit(2);
-            }
-            SpotifyError::InternalError(err) => {
-                println!("Internal Error: {:?}", err);
-                std::process::exit(3);
-            }
-        },
+        Err(error) => exit_with(error),
     };
-    let reactor = spotify.poll(|_client, status, change| {
+    if let Some(command) = command {
+        run_command(&spotify, command);
+    }
+    if once {
+        let status = match spotify.status_with_retry(3, Duration::from_millis(250)) {
+            Ok(status) => status,
+            Err(error) => exit_with(error),
+        };
+        if json {
+            println!("{}", JsonValue::from(&status).dump());
+        } else if status.is_playing() {
+            println!("Now playing: {:#}", status.track());
+            println!("{}", status.full_track().track.uri);
+        }
+        match status.is_playing() {
+            true => return,
+            // Keep the output parseable, the status tells anyway.
+            false if json => std::process::exit(6),
+            false => exit_with(SpotifyError::NothingPlaying),
+        }
+    }
+    let reactor = spotify.poll(move |_client, status, change| {
+        if json {
+            if change.is_user_visible() {
+                println!("{}", JsonValue::from(&status).dump());
+            }
+            return true;
+        }
         if change.client_version {
             println!("Spotify Client (Version {})", status.version());
         }
@@ -29,8 +74,106 @@ fn main() {
         }
         true
     });
-    if reactor.join().ok().is_none() {
-        println!("Unable to join into the live-update.");
+    if reactor
+        .ok()
+        .and_then(|reactor| reactor.join().ok())
+        .is_none()
+    {
+        println!("Unable to start or join into the live-update.");
         std::process::exit(4);
     }
 }
+
+/// Parses the subcommand, if any.
+/// Exits with the usage if the arguments are invalid.
+fn parse_command(args: &[String]) -> Option<Command> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let command = match args[..] {
+        [] => return None,
+        ["play", uri] => Command::Play(uri.to_owned()),
+        ["pause"] => Command::Pause,
+        ["resume"] => Command::Resume,
+        ["next"] => Command::Next,
+        ["prev"] => Command::Prev,
+        ["volume", volume] => match volume.parse::<u8>() {
+            Ok(volume) if volume <= 100 => Command::Volume(f32::from(volume) / 100_f32),
+            _ => exit_with_usage(),
+        },
+        _ => exit_with_usage(),
+    };
+    Some(command)
+}
+
+/// Issues the specified command and exits with the matching code.
+fn run_command(spotify: &Spotify, command: Command) -> ! {
+    let result = match command {
+        Command::Play(uri) => Ok(spotify.play(uri)),
+        Command::Pause => Ok(spotify.pause()),
+        Command::Resume => Ok(spotify.resume()),
+        Command::Next => spotify.next().map(|_| true),
+        Command::Prev => spotify.prev().map(|_| true),
+        Command::Volume(volume) => spotify
+            .set_state(DesiredState {
+                volume: Some(volume),
+                ..DesiredState::default()
+            })
+            .map(|_| true),
+    };
+    match result {
+        Ok(true) => std::process::exit(0),
+        Ok(false) => {
+            println!("The command failed!");
+            std::process::exit(11);
+        }
+        Err(error) => exit_with(error),
+    }
+}
+
+/// Prints the usage and exits.
+fn exit_with_usage() -> ! {
+    println!("{}", USAGE);
+    std::process::exit(12);
+}
+
+/// Prints the specified error and exits with the matching code.
+fn exit_with(error: SpotifyError) -> ! {
+    match error {
+        SpotifyError::ClientNotRunning => {
+            println!("The Spotify Client is not running!");
+            std::process::exit(1);
+        }
+        SpotifyError::WebHelperNotRunning => {
+            println!("The SpotifyWebHelper process is not running!");
+            std::process::exit(2);
+        }
+        error @ SpotifyError::InternalError(_) => {
+            println!("Error: {}", error);
+            std::process::exit(3);
+        }
+        SpotifyError::Timeout => {
+            println!("Timed out while waiting for Spotify!");
+            std::process::exit(5);
+        }
+        SpotifyError::NothingPlaying => {
+            println!("Nothing is playing!");
+            std::process::exit(6);
+        }
+        SpotifyError::TrackChanged => {
+            println!("The track changed unexpectedly!");
+            std::process::exit(7);
+        }
+        SpotifyError::Unsupported(capability) => {
+            println!("Unsupported by the Spotify Client: {:?}", capability);
+            std::process::exit(8);
+        }
+        error @ SpotifyError::ConnectFailed(_) => {
+            println!("Error: {}", error);
+            std::process::exit(9);
+        }
+        // Variants added in later versions.
+        error => {
+            println!("Error: {}", error);
+            std::process::exit(10);
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! The builder module.
+//!
+//! This module contains the `SpotifyBuilder`, which is used
+//! to configure how the connection to Spotify is established.
+
+use crate::connector::ConnectorConfig;
+#[cfg(feature = "async")]
+use crate::connector::InternalSpotifyError;
+#[cfg(all(windows, feature = "windows-process"))]
+use crate::SpotifyError;
+use crate::{Result, Spotify, TrackUri};
+#[cfg(feature = "async")]
+use futures::channel::oneshot;
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::io;
+use std::net::IpAddr;
+#[cfg(feature = "async")]
+use std::thread;
+use std::time::Duration;
+
+/// A builder for configuring a `Spotify` connection.
+///
+/// Settings made on the builder take precedence over the
+/// `SPOTIFY_LOCAL_HOST` and `SPOTIFY_LOCAL_PORT` environment
+/// variables, which in turn take precedence over auto-detection.
+#[derive(Debug, Clone, Default)]
+pub struct SpotifyBuilder {
+    /// The connector configuration.
+    config: ConnectorConfig,
+}
+
+/// Implements `SpotifyBuilder`.
+impl SpotifyBuilder {
+    /// Constructs a new `SpotifyBuilder` with default settings.
+    pub fn new() -> SpotifyBuilder {
+        SpotifyBuilder::default()
+    }
+    /// Sets the host name of the local Spotify server.
+    /// Defaults to `spotifyrs.spotilocal.com`, falling back to
+    /// `127.0.0.1` and `[::1]` if it does not respond.
+    pub fn host(mut self, host: &str) -> SpotifyBuilder {
+        self.config.host = Some(host.to_owned());
+        self
+    }
+    /// Sets the address the local Spotify server is reached at,
+    /// which is also used to auto-detect the port.
+    /// Defaults to the loopback addresses.
+    ///
+    /// This allows controlling a client on another machine through
+    /// an SSH tunnel, e.g. `ssh -L 0.0.0.0:4370:127.0.0.1:4370 htpc`
+    /// forwarded to an address of this machine. Since the
+    /// `spotilocal.com` host name never resolves to the tunnel, the
+    /// address is used as host, unless a host is set explicitly.
+    pub fn local_addr(mut self, addr: IpAddr) -> SpotifyBuilder {
+        self.config.local_addr = Some(addr);
+        self
+    }
+    /// Sets the port of the local Spotify server,
+    /// bypassing port auto-detection.
+    pub fn port(mut self, port: u16) -> SpotifyBuilder {
+        self.config.port = Some(port);
+        self
+    }
+    /// Sets the maximum duration of the whole connect sequence,
+    /// including port detection, opening the client and fetching
+    /// the tokens. Connecting fails with `SpotifyError::Timeout`
+    /// once it is exceeded. Later requests are not affected.
+    pub fn connect_timeout(mut self, timeout: Duration) -> SpotifyBuilder {
+        self.config.connect_timeout = Some(timeout);
+        self
+    }
+    /// Sets whether the local Spotify server is reached over HTTPS.
+    /// Defaults to plain HTTP.
+    pub fn https(mut self, https: bool) -> SpotifyBuilder {
+        self.config.https = https;
+        self
+    }
+    /// Sets whether connecting requires the OAuth token.
+    /// Defaults to true.
+    ///
+    /// The OAuth token is fetched from `open.spotify.com`, which needs
+    /// an internet connection. If not required, connecting proceeds
+    /// with the CSRF token only when fetching it fails, which keeps
+    /// local control working offline. Requests are then sent without
+    /// the OAuth token, so end-points requiring it fail with an error.
+    pub fn require_oauth(mut self, required: bool) -> SpotifyBuilder {
+        self.config.optional_oauth = !required;
+        self
+    }
+    /// Enables a background thread issuing a lightweight request
+    /// at the specified interval, refreshing the tokens if it fails.
+    ///
+    /// This keeps the connection of an otherwise idle client working,
+    /// e.g. one that only sends commands when a button is pressed.
+    /// The thread ends once the connection is dropped.
+    pub fn keepalive(mut self, interval: Duration) -> SpotifyBuilder {
+        self.config.keepalive = Some(interval);
+        self
+    }
+    /// Sets the maximum size of a response body in bytes.
+    /// Larger responses are rejected instead of being read into memory.
+    /// Defaults to 4 MiB.
+    pub fn max_response_size(mut self, size: usize) -> SpotifyBuilder {
+        self.config.max_response_size = Some(size);
+        self
+    }
+    /// Sets the user agent sent with every request.
+    /// Defaults to a Firefox 50 user agent.
+    pub fn user_agent(mut self, user_agent: &str) -> SpotifyBuilder {
+        self.config.user_agent = Some(user_agent.to_owned());
+        self
+    }
+    /// Sets the track sent as embed referer with every request.
+    /// Defaults to `spotify:track:4uLU6hMCjMI75M1A2tKUQC`.
+    ///
+    /// Some requests are rejected unless the referer is
+    /// a valid embeddable track.
+    pub fn referal_track(mut self, track: TrackUri) -> SpotifyBuilder {
+        self.config.referal_track = Some(format!("track/{}", track.id()));
+        self
+    }
+    /// Connects to the local Spotify client.
+    #[cfg(all(windows, feature = "windows-process"))]
+    pub fn connect(self) -> Result<Spotify> {
+        // TODO:
+        // At some point, the connector should automatically
+        // open Spotify in the case  that Spotify is closed.
+        // That would also be a much better cross-platform solution,
+        // because it would work on Linux and macOS and make
+        // the dependency on winapi and kernel32-sys unnecessary.
+        if !Spotify::spotify_webhelper_alive() {
+            return Err(SpotifyError::WebHelperNotRunning);
+        }
+        Spotify::new_unchecked(&self.config)
+    }
+    /// Connects to the local Spotify client.
+    ///
+    /// The SpotifyWebHelper process check is skipped, because it is
+    /// only available on Windows with the `windows-process` feature.
+    #[cfg(not(all(windows, feature = "windows-process")))]
+    pub fn connect(self) -> Result<Spotify> {
+        Spotify::new_unchecked(&self.config)
+    }
+    /// Connects to the local Spotify client on a dedicated thread,
+    /// without blocking the calling task.
+    ///
+    /// This is not an asynchronous handshake: the blocking `connect`
+    /// is offloaded to a new thread named `spotify-connect`, since the
+    /// async client of the underlying HTTP library is not compatible
+    /// with `std::future`. The returned future resolves once that
+    /// thread is done, so no runtime worker is blocked meanwhile.
+    ///
+    /// Only available with the `async` feature enabled.
+    #[cfg(feature = "async")]
+    pub fn connect_offloaded(self) -> impl Future<Output = Result<Spotify>> {
+        let (sender, receiver) = oneshot::channel();
+        let spawned = thread::Builder::new()
+            .name("spotify-connect".to_owned())
+            .spawn(move || {
+                let _ = sender.send(self.connect());
+            });
+        async move {
+            if let Err(error) = spawned {
+                return Err(InternalSpotifyError::from(error).into());
+            }
+            match receiver.await {
+                Ok(result) => result,
+                Err(_) => {
+                    let error = io::Error::other("the connect thread panicked");
+                    Err(InternalSpotifyError::from(error).into())
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use super::SpotifyBuilder;
+    use crate::test_server::{config_for, spotify_routes, TestServer};
+    use crate::SpotifyError;
+
+    #[test]
+    fn connect_offloaded_resolves_to_a_connection() {
+        let server = TestServer::start(spotify_routes());
+        let builder = SpotifyBuilder {
+            config: config_for(&server),
+        };
+        let spotify = futures::executor::block_on(builder.connect_offloaded()).unwrap();
+        assert_eq!(spotify.status().unwrap().version(), "1.0.42.151.g19de0aa6");
+    }
+
+    #[test]
+    fn connect_offloaded_resolves_to_the_connect_error() {
+        let mut routes = spotify_routes();
+        routes.retain(|route| route.0 != "/simplecsrf/token.json");
+        let server = TestServer::start(routes);
+        let builder = SpotifyBuilder {
+            config: config_for(&server),
+        };
+        let result = futures::executor::block_on(builder.connect_offloaded());
+        assert!(matches!(result, Err(SpotifyError::InternalError(_))));
+    }
+}
@@ -1,9 +1,16 @@
 use json::{self, JsonValue};
 use reqwest::header::{ORIGIN, REFERER, USER_AGENT};
 use reqwest::{self, Client};
-use std::io::Read;
-use std::net::TcpListener;
-use std::sync::Mutex;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::net::{IpAddr, TcpListener};
+use std::ops::Range;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use url::form_urlencoded;
 
 // Headers
 const HEADER_UA: &str = "Mozilla/5.0 (Windows; rv:50.0) Gecko/20100101 Firefox/50.0";
@@ -13,18 +20,33 @@ const HEADER_ORIGIN_HOST: &str = "embed.spotify.com";
 // Spotify base URLs
 const URL_EMBED: &str = "https://embed.spotify.com";
 const URL_TOKEN: &str = "https://open.spotify.com/token";
-const URL_LOCAL: &str = "http://spotifyrs.spotilocal.com";
+const URL_LOCAL_SCHEME: &str = "http";
+const URL_LOCAL_SCHEME_TLS: &str = "https";
+const URL_LOCAL_HOST: &str = "spotifyrs.spotilocal.com";
+const URL_LOCAL_FALLBACK_HOSTS: &[&str] = &["127.0.0.1", "[::1]"];
+
+// Environment variables overriding the local host and port
+const ENV_LOCAL_HOST: &str = "SPOTIFY_LOCAL_HOST";
+const ENV_LOCAL_PORT: &str = "SPOTIFY_LOCAL_PORT";
 
 // Spotify local ports
 const PORT_START: u16 = 4370;
 const PORT_END: u16 = 4399;
 
-// Spotify request end-points
-const REQUEST_CSRF: &str = "simplecsrf/token.json";
-const REQUEST_STATUS: &str = "remote/status.json";
-const REQUEST_PLAY: &str = "remote/play.json";
-const REQUEST_OPEN: &str = "remote/open.json";
-const REQUEST_PAUSE: &str = "remote/pause.json";
+// The encodings of a boolean parameter, in the order they are tried
+const BOOL_PARAMS: &[(&str, &str)] = &[("true", "false"), ("1", "0")];
+
+// The timeout of a single port probe
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+// The delay before retrying to open a client that is not running
+const START_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+// The default maximum size of a response body (4 MiB)
+const MAX_RESPONSE_SIZE: usize = 4 * 1024 * 1024;
+
+// Known key paths of the CSRF token, in order of preference
+const CSRF_TOKEN_PATHS: &[&[&str]] = &[&["token"], &["csrf"], &["csrf", "token"], &["value"]];
 
 // The referal track
 const REFERAL_TRACK: &str = "track/4uLU6hMCjMI75M1A2tKUQC";
@@ -33,7 +55,11 @@ const REFERAL_TRACK: &str = "track/4uLU6hMCjMI75M1A2tKUQC";
 type Result<T> = ::std::result::Result<T, InternalSpotifyError>;
 
 /// The `InternalSpotifyError` enum.
+///
+/// New variants may be added in minor releases,
+/// so matches on it need a catch-all arm.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum InternalSpotifyError {
     // Reqwest
     ReqwestError(reqwest::Error),
@@ -43,78 +69,450 @@ pub enum InternalSpotifyError {
     InvalidOAuthToken,
     // CSRF
     InvalidCSRFToken,
+    // Status
+    UnexpectedResponse(JsonValue),
+    // Deadline
+    Timeout,
+    // Client
+    ClientNotRunning,
+    // Body size limit
+    ResponseTooLarge,
+    // D-Bus
+    #[cfg(all(target_os = "linux", feature = "mpris"))]
+    DBusError(zbus::Error),
     // Other
     IOError(::std::io::Error),
 }
 
+/// Implements `InternalSpotifyError`.
+impl InternalSpotifyError {
+    /// Gets a value indicating whether the error was caused
+    /// by the local server refusing the connection.
+    pub fn is_connection_refused(&self) -> bool {
+        let mut source: Option<&(dyn Error + 'static)> = match self {
+            InternalSpotifyError::ReqwestError(error) => match error.get_ref() {
+                Some(error) => Some(error),
+                None => None,
+            },
+            InternalSpotifyError::IOError(error) => Some(error),
+            _ => None,
+        };
+        while let Some(error) = source {
+            if let Some(error) = error.downcast_ref::<::std::io::Error>() {
+                if error.kind() == ::std::io::ErrorKind::ConnectionRefused {
+                    return true;
+                }
+            }
+            source = error.source();
+        }
+        false
+    }
+    /// Gets a value indicating whether the error may go away
+    /// when retrying, i.e. a failed or timed out request,
+    /// or a response that could not be read or parsed.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            InternalSpotifyError::ReqwestError(_)
+                | InternalSpotifyError::JSONParseError(_)
+                | InternalSpotifyError::IOError(_)
+                | InternalSpotifyError::Timeout
+        )
+    }
+}
+
+/// Implements `fmt::Display` for `InternalSpotifyError`.
+impl fmt::Display for InternalSpotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InternalSpotifyError::ReqwestError(error) => write!(f, "request failed: {}", error),
+            InternalSpotifyError::JSONParseError(error) => {
+                write!(f, "invalid JSON response: {}", error)
+            }
+            InternalSpotifyError::InvalidOAuthToken => f.write_str("invalid OAuth token"),
+            InternalSpotifyError::InvalidCSRFToken => f.write_str("invalid CSRF token"),
+            InternalSpotifyError::UnexpectedResponse(json) => {
+                write!(f, "unexpected response: {}", json)
+            }
+            InternalSpotifyError::Timeout => f.write_str("deadline exceeded"),
+            InternalSpotifyError::ClientNotRunning => f.write_str("client not running"),
+            InternalSpotifyError::ResponseTooLarge => f.write_str("response body too large"),
+            #[cfg(all(target_os = "linux", feature = "mpris"))]
+            InternalSpotifyError::DBusError(error) => write!(f, "D-Bus error: {}", error),
+            InternalSpotifyError::IOError(error) => write!(f, "I/O error: {}", error),
+        }
+    }
+}
+
+/// Implements `Error` for `InternalSpotifyError`.
+impl Error for InternalSpotifyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InternalSpotifyError::ReqwestError(error) => Some(error),
+            InternalSpotifyError::JSONParseError(error) => Some(error),
+            #[cfg(all(target_os = "linux", feature = "mpris"))]
+            InternalSpotifyError::DBusError(error) => Some(error),
+            InternalSpotifyError::IOError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Implements `From<reqwest::Error>` for `InternalSpotifyError`.
+impl From<reqwest::Error> for InternalSpotifyError {
+    fn from(error: reqwest::Error) -> InternalSpotifyError {
+        InternalSpotifyError::ReqwestError(error)
+    }
+}
+
+/// Implements `From<json::Error>` for `InternalSpotifyError`.
+impl From<json::Error> for InternalSpotifyError {
+    fn from(error: json::Error) -> InternalSpotifyError {
+        InternalSpotifyError::JSONParseError(error)
+    }
+}
+
+/// Implements `From<io::Error>` for `InternalSpotifyError`.
+impl From<io::Error> for InternalSpotifyError {
+    fn from(error: io::Error) -> InternalSpotifyError {
+        InternalSpotifyError::IOError(error)
+    }
+}
+
+/// The `Endpoint` enum.
+/// Lists all Spotify end-points known to the connector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoint {
+    /// Fetches the OAuth token from `open.spotify.com`.
+    Token,
+    /// Fetches the CSRF token from the local server.
+    Csrf,
+    /// Fetches the current status.
+    Status,
+    /// Plays a track.
+    Play,
+    /// Opens (and possibly starts) the client.
+    Open,
+    /// Pauses or resumes playback.
+    Pause,
+}
+
+/// Implements `Endpoint`.
+impl Endpoint {
+    /// Gets the path of the end-point, relative to its base url.
+    pub fn path(self) -> &'static str {
+        match self {
+            Endpoint::Token => "",
+            Endpoint::Csrf => "simplecsrf/token.json",
+            Endpoint::Status => "remote/status.json",
+            Endpoint::Play => "remote/play.json",
+            Endpoint::Open => "remote/open.json",
+            Endpoint::Pause => "remote/pause.json",
+        }
+    }
+}
+
+/// The `ConnectorConfig` struct.
+///
+/// Unset fields fall back to the corresponding environment
+/// variable, and then to auto-detection.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectorConfig {
+    /// The local host name.
+    pub host: Option<String>,
+    /// The local port.
+    pub port: Option<u16>,
+    /// The address the local server is reached at,
+    /// e.g. the listening side of an SSH tunnel.
+    pub local_addr: Option<IpAddr>,
+    /// The url the OAuth token is fetched from.
+    pub token_url: Option<String>,
+    /// The embed path of the referal track, e.g. `track/<id>`.
+    pub referal_track: Option<String>,
+    /// The user agent sent with every request.
+    pub user_agent: Option<String>,
+    /// The maximum duration of the whole connect sequence.
+    pub connect_timeout: Option<Duration>,
+    /// The maximum size of a response body in bytes.
+    pub max_response_size: Option<usize>,
+    /// Whether the local server is reached over HTTPS.
+    pub https: bool,
+    /// Whether connecting proceeds without an OAuth token
+    /// when fetching it fails, e.g. while offline.
+    pub optional_oauth: bool,
+    /// The interval of the keepalive requests, if enabled.
+    pub keepalive: Option<Duration>,
+}
+
 /// The `SpotifyConnector` struct.
 pub struct SpotifyConnector {
-    /// The Reqwest client.
+    /// The Reqwest client used for local requests.
     client: Mutex<Client>,
+    /// The Reqwest client used to fetch the OAuth token.
+    /// Unlike the local client, it honors the system proxy settings.
+    token_client: Client,
+    /// The host used to connect to Spotify.
+    host: String,
+    /// The url the OAuth token is fetched from.
+    token_url: String,
+    /// The embed path of the track sent as referer.
+    referal_track: String,
+    /// The user agent sent with every request.
+    user_agent: String,
     /// The Spotify OAuth token.
-    oauth_token: String,
+    /// Replaced when the tokens are refreshed.
+    oauth_token: RwLock<Arc<str>>,
     /// The Spotify CSRF token.
-    csrf_token: String,
+    /// Replaced when the tokens are refreshed.
+    csrf_token: RwLock<Arc<str>>,
+    /// Whether the OAuth token may be missing.
+    optional_oauth: bool,
     /// The port used to connect to Spotify.
-    port: i32,
+    port: u16,
+    /// The address the local server is reached at, if not the loopback.
+    local_addr: Option<IpAddr>,
+    /// The deadline all requests have to finish by.
+    /// Only set while connecting.
+    deadline: Option<Instant>,
+    /// The maximum size of a response body in bytes.
+    max_response_size: usize,
+    /// The scheme of the local server.
+    scheme: &'static str,
+}
+
+/// Parses a JSON response body.
+fn parse_response(response: &str) -> Result<JsonValue> {
+    Ok(json::parse(response)?)
+}
+
+/// Parses a status response body, which has to be a JSON object.
+pub fn parse_status_response(response: &str) -> Result<JsonValue> {
+    let json = parse_response(response)?;
+    if !json.is_object() {
+        return Err(InternalSpotifyError::UnexpectedResponse(json));
+    }
+    Ok(json)
 }
 
 /// Implements `SpotifyConnector`.
 impl SpotifyConnector {
     /// Constructs a new `SpotifyConnector`.
     /// Retrieves the OAuth and CSRF tokens in the process.
-    pub fn connect_new() -> Result<SpotifyConnector> {
-        // Create the reqwest client.
-        let client = Client::new();
+    ///
+    /// The host and port are taken from the config if set,
+    /// then from the `SPOTIFY_LOCAL_HOST` and `SPOTIFY_LOCAL_PORT`
+    /// environment variables, and are auto-detected otherwise.
+    ///
+    /// Without an explicit host, the local server is looked up at
+    /// `spotifyrs.spotilocal.com`, then at `127.0.0.1` and finally
+    /// at `[::1]`. The first host that responds is used from then on.
+    ///
+    /// The OAuth token is fetched from `open.spotify.com` using the
+    /// system proxy settings (`HTTP_PROXY`/`HTTPS_PROXY`), whereas
+    /// local requests always bypass the proxy.
+    ///
+    /// If the config sets a connect timeout, the whole sequence
+    /// fails with `Timeout` once it is exceeded.
+    pub fn connect_new(config: &ConnectorConfig) -> Result<SpotifyConnector> {
+        // Collect the local hosts to try.
+        let hosts = local_hosts(explicit_host(config));
         // Create the connector.
-        let mut connector = SpotifyConnector {
-            client: Mutex::new(client),
-            oauth_token: String::default(),
-            csrf_token: String::default(),
-            port: 0, // will be populated later
-        };
-        connector.update_port();
+        let mut connector = SpotifyConnector::new(config, &hosts[0])?;
+        match config
+            .port
+            .or_else(|| env::var(ENV_LOCAL_PORT).ok()?.parse().ok())
+        {
+            Some(port) => connector.port = port,
+            None => connector.update_port(),
+        }
         // Connect to SpotifyWebHelper and start Spotify.
-        connector.start_spotify()?;
-        // Fetch the OAuth token.
-        connector.oauth_token = match connector.fetch_oauth_token() {
-            Ok(result) => result,
-            Err(error) => return Err(error),
-        };
-        // Fetch the CSRF token.
-        connector.csrf_token = match connector.fetch_csrf_token() {
-            Ok(result) => result,
-            Err(error) => return Err(error),
-        };
+        // A client that is not running yet gets one more chance.
+        if !connector.start_spotify_on_any(&hosts)? {
+            connector.sleep_until_deadline(START_RETRY_DELAY)?;
+            if !connector.start_spotify()? {
+                return Err(InternalSpotifyError::ClientNotRunning);
+            }
+        }
+        // Fetch the OAuth and CSRF tokens.
+        connector.refresh_tokens()?;
+        // Lift the deadline for all further requests.
+        connector.deadline = None;
         // Return the connector.
         Ok(connector)
     }
+    /// Constructs a new `SpotifyConnector` using the specified host,
+    /// without connecting to it.
+    fn new(config: &ConnectorConfig, host: &str) -> Result<SpotifyConnector> {
+        let deadline = config
+            .connect_timeout
+            .map(|timeout| Instant::now() + timeout);
+        // Create the reqwest clients.
+        let client = Client::new();
+        let token_client = Client::builder().use_sys_proxy().build()?;
+        Ok(SpotifyConnector {
+            client: Mutex::new(client),
+            token_client,
+            host: host.to_owned(),
+            token_url: config
+                .token_url
+                .clone()
+                .unwrap_or_else(|| URL_TOKEN.to_owned()),
+            referal_track: config
+                .referal_track
+                .clone()
+                .unwrap_or_else(|| REFERAL_TRACK.to_owned()),
+            user_agent: config
+                .user_agent
+                .clone()
+                .unwrap_or_else(|| HEADER_UA.to_owned()),
+            oauth_token: RwLock::new(Arc::from("")),
+            csrf_token: RwLock::new(Arc::from("")),
+            optional_oauth: config.optional_oauth,
+            port: 0, // will be populated later
+            local_addr: config.local_addr,
+            deadline,
+            max_response_size: config.max_response_size.unwrap_or(MAX_RESPONSE_SIZE),
+            scheme: if config.https {
+                URL_LOCAL_SCHEME_TLS
+            } else {
+                URL_LOCAL_SCHEME
+            },
+        })
+    }
+    /// Probes each of the specified local ports for a CSRF token,
+    /// without connecting. Returns each port and whether it responded.
+    ///
+    /// The host is taken from the config or the `SPOTIFY_LOCAL_HOST`
+    /// environment variable, and defaults to `127.0.0.1`.
+    /// Each probe times out after `PROBE_TIMEOUT`.
+    pub fn scan_ports(config: &ConnectorConfig, ports: Range<u16>) -> Result<Vec<(u16, bool)>> {
+        let host = explicit_host(config).unwrap_or_else(|| URL_LOCAL_FALLBACK_HOSTS[0].to_owned());
+        let mut connector = SpotifyConnector::new(config, &host)?;
+        Ok(ports
+            .map(|port| {
+                connector.port = port;
+                connector.deadline = Some(Instant::now() + PROBE_TIMEOUT);
+                (port, connector.fetch_csrf_token().is_ok())
+            })
+            .collect())
+    }
+    /// Gets the range of ports the local server may listen on.
+    pub fn port_range() -> Range<u16> {
+        PORT_START..PORT_END
+    }
+    /// Gets the Spotify OAuth token.
+    pub fn oauth_token(&self) -> Arc<str> {
+        self.oauth_token.read().unwrap().clone()
+    }
+    /// Gets the Spotify CSRF token.
+    pub fn csrf_token(&self) -> Arc<str> {
+        self.csrf_token.read().unwrap().clone()
+    }
+    /// Fetches new OAuth and CSRF tokens, replacing the current ones.
+    /// If the OAuth token is optional, failing to fetch it leaves it empty.
+    pub fn refresh_tokens(&self) -> Result<()> {
+        let oauth_token = match self.fetch_oauth_token() {
+            Err(ref error) if self.optional_oauth => {
+                warn!(
+                    "Unable to fetch the OAuth token, continuing without: {}",
+                    error
+                );
+                String::default()
+            }
+            result => result?,
+        };
+        let csrf_token = self.fetch_csrf_token()?;
+        *self.oauth_token.write().unwrap() = oauth_token.into();
+        *self.csrf_token.write().unwrap() = csrf_token.into();
+        Ok(())
+    }
+    /// Issues a status request to keep the connection alive,
+    /// refreshing the tokens if it fails.
+    pub fn keep_alive(&self) -> Result<()> {
+        match self.fetch_status_json() {
+            Ok(_) => Ok(()),
+            Err(error) => {
+                debug!("Keepalive request failed, refreshing the tokens: {}", error);
+                self.refresh_tokens()
+            }
+        }
+    }
+    /// Gets the port used to connect to Spotify.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+    /// Gets the scheme of the local server.
+    pub fn scheme(&self) -> &'static str {
+        self.scheme
+    }
     /// Updates the local Spotify port.
+    /// A port counts as taken if it is in use on `127.0.0.1` or `::1`,
+    /// or on the configured local address if there is one.
     fn update_port(&mut self) {
-        for port in PORT_START..PORT_END {
-            if TcpListener::bind(("127.0.0.1", port)).is_err() {
-                self.port = port as i32;
+        for port in SpotifyConnector::port_range() {
+            let taken = match self.local_addr {
+                Some(addr) => TcpListener::bind((addr, port)).is_err(),
+                None => TcpListener::bind(("127.0.0.1", port)).is_err() || is_in_use(("::1", port)),
+            };
+            if taken {
+                self.port = port;
                 return;
             }
         }
     }
     /// Constructs the local Spotify url.
-    fn get_local_url(&self) -> String {
-        format!("{}:{}", URL_LOCAL, self.port)
+    pub fn get_local_url(&self) -> String {
+        format!("{}://{}:{}", self.scheme, self.host, self.port)
+    }
+    /// Gets the base url of the specified end-point.
+    fn get_base_url(&self, endpoint: Endpoint) -> String {
+        match endpoint {
+            Endpoint::Token => self.token_url.clone(),
+            _ => self.get_local_url(),
+        }
+    }
+    /// Sleeps for the specified delay, but not past the deadline.
+    /// Fails with `Timeout` if the deadline has already passed.
+    fn sleep_until_deadline(&self, delay: Duration) -> Result<()> {
+        let delay = match self.deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining == Duration::from_secs(0) {
+                    return Err(InternalSpotifyError::Timeout);
+                }
+                delay.min(remaining)
+            }
+            None => delay,
+        };
+        thread::sleep(delay);
+        Ok(())
     }
     /// Attempts to start the Spotify client.
+    ///
+    /// The `open` end-point has no known parameter to control the
+    /// window focus, so the client may be brought to the foreground.
     fn start_spotify(&self) -> Result<bool> {
-        match self.query(&self.get_local_url(), REQUEST_OPEN, false, false, None) {
-            Ok(result) => Ok(result["running"] == true),
-            Err(error) => Err(error),
+        let json = self.query(Endpoint::Open, false, false, &[])?;
+        Ok(json["running"] == true)
+    }
+    /// Attempts to start the Spotify client using each of the
+    /// specified hosts in order, keeping the first one that responds.
+    /// Fails with the error of the last host if none responds.
+    fn start_spotify_on_any(&mut self, hosts: &[String]) -> Result<bool> {
+        let (last, rest) = hosts.split_last().expect("no local hosts");
+        for host in rest {
+            self.host = host.clone();
+            match self.start_spotify() {
+                Ok(running) => return Ok(running),
+                Err(error) => debug!("Unable to reach the local server at {}: {}", host, error),
+            }
         }
+        self.host = last.clone();
+        self.start_spotify()
     }
     /// Fetches the OAuth token from Spotify.
     fn fetch_oauth_token(&self) -> Result<String> {
-        let json = match self.query(URL_TOKEN, "", false, false, None) {
-            Ok(result) => result,
-            Err(error) => return Err(error),
-        };
+        let json = self.query(Endpoint::Token, false, false, &[])?;
         match json["t"].as_str() {
             Some(token) => Ok(token.to_owned()),
             None => Err(InternalSpotifyError::InvalidOAuthToken),
@@ -122,101 +520,567 @@ impl SpotifyConnector {
     }
     /// Fetches the CSRF token from Spotify.
     fn fetch_csrf_token(&self) -> Result<String> {
-        let json = match self.query(&self.get_local_url(), REQUEST_CSRF, false, false, None) {
-            Ok(result) => result,
-            Err(error) => return Err(error),
-        };
-        match json["token"].as_str() {
-            Some(token) => Ok(token.to_owned()),
+        let json = self.query(Endpoint::Csrf, false, false, &[])?;
+        match find_csrf_token(&json) {
+            Some((path, token)) => {
+                debug!("Found CSRF token at key path {:?}", path);
+                Ok(token.to_owned())
+            }
             None => Err(InternalSpotifyError::InvalidCSRFToken),
         }
     }
     /// Fetches the current status from Spotify.
+    /// Fails if the response is not a JSON object.
     pub fn fetch_status_json(&self) -> Result<JsonValue> {
-        self.query(&self.get_local_url(), REQUEST_STATUS, true, true, None)
+        let (body, _) = self.fetch_status_json_timed()?;
+        parse_status_response(&body)
     }
     /// Requests a track to be played.
     pub fn request_play(&self, track: String) -> bool {
-        let params = vec![format!("uri={0}", track)];
-        self.query(
-            &self.get_local_url(),
-            REQUEST_PLAY,
-            true,
-            true,
-            Some(params),
-        )
-        .is_ok()
+        self.try_request_play(track).is_ok()
+    }
+    /// Requests a track to be played, like `request_play`,
+    /// but fails with the error of the request.
+    pub fn try_request_play(&self, track: String) -> Result<()> {
+        let params = [("uri", track.as_str())];
+        self.query(Endpoint::Play, true, true, &params)?;
+        Ok(())
+    }
+    /// Requests a track to be played from the specified position.
+    /// The position is passed as `#m:ss` fragment of the track uri.
+    pub fn request_seek(&self, track: String, position: Duration) -> bool {
+        let secs = position.as_secs();
+        let uri = format!("{}#{}:{:02}", track, secs / 60, secs % 60);
+        self.query(Endpoint::Play, true, true, &[("uri", &uri)])
+            .is_ok()
+    }
+    /// Requests a track to be played within the specified context,
+    /// so that playback continues through the context afterwards.
+    pub fn request_play_in_context(&self, track: String, context: String) -> bool {
+        let params = [("uri", track.as_str()), ("context", context.as_str())];
+        self.query(Endpoint::Play, true, true, &params).is_ok()
     }
     /// Requests the currently playing track to be paused or resumed.
+    ///
+    /// Some client versions ignore the lowercase `true`/`false` form,
+    /// so if the returned status shows no effect, the request is
+    /// repeated using the alternative `1`/`0` form.
     pub fn request_pause(&self, pause: bool) -> bool {
-        let params = vec![format!("pause={}", pause)];
-        self.query(
-            &self.get_local_url(),
-            REQUEST_PAUSE,
+        self.try_request_pause(pause).is_ok()
+    }
+    /// Requests the currently playing track to be paused or resumed,
+    /// like `request_pause`, but fails with the error of the request.
+    pub fn try_request_pause(&self, pause: bool) -> Result<()> {
+        for &(on, off) in BOOL_PARAMS {
+            let value = if pause { on } else { off };
+            let json = self.query(Endpoint::Pause, true, true, &[("pause", value)])?;
+            if !json.has_key("playing") || json["playing"] != pause {
+                break;
+            }
+        }
+        Ok(())
+    }
+    /// Fetches the current status from Spotify.
+    /// Returns the raw response body together with the HTTP latency.
+    pub fn fetch_status_json_timed(&self) -> Result<(String, Duration)> {
+        let start = Instant::now();
+        let endpoint = Endpoint::Status;
+        let body = self.query_body(
+            &self.get_base_url(endpoint),
+            endpoint.path(),
             true,
             true,
-            Some(params),
-        )
-        .is_ok()
+            &[],
+        )?;
+        Ok((body, start.elapsed()))
     }
-    /// Queries the specified base url with the specified query.
+    /// Queries the specified local end-point with the specified parameters.
+    /// The parameter values are percent-encoded.
+    pub fn query_raw(
+        &self,
+        endpoint: &str,
+        params: &[(&str, &str)],
+        with_oauth: bool,
+        with_csrf: bool,
+    ) -> Result<JsonValue> {
+        let response = self.query_body(
+            &self.get_local_url(),
+            endpoint,
+            with_oauth,
+            with_csrf,
+            params,
+        )?;
+        parse_response(&response)
+    }
+    /// Queries the specified end-point.
     /// Optionally includes the OAuth and/or CSRF token in the query.
     fn query(
+        &self,
+        endpoint: Endpoint,
+        with_oauth: bool,
+        with_csrf: bool,
+        params: &[(&str, &str)],
+    ) -> Result<JsonValue> {
+        let response = self.query_body(
+            &self.get_base_url(endpoint),
+            endpoint.path(),
+            with_oauth,
+            with_csrf,
+            params,
+        )?;
+        parse_response(&response)
+    }
+    /// Builds the url for the specified base url and query.
+    /// The parameter values are percent-encoded.
+    fn query_url(
         &self,
         base: &str,
         query: &str,
         with_oauth: bool,
         with_csrf: bool,
-        params: Option<Vec<String>>,
-    ) -> Result<JsonValue> {
-        let timestamp = time::now_utc().to_timespec().sec;
-        let arguments = {
-            let mut arguments = String::new();
-            if !query.contains('?') {
-                arguments.push('?');
-            }
-            arguments.push_str("&ref=&cors=");
-            arguments.push_str(format!("&_={}", timestamp).as_ref());
-            if with_oauth {
-                arguments.push_str(format!("&oauth={}", self.oauth_token).as_ref());
+        params: &[(&str, &str)],
+    ) -> String {
+        let timestamp = time::now_utc().to_timespec().sec.to_string();
+        let mut arguments = form_urlencoded::Serializer::new(String::new());
+        arguments
+            .append_pair("ref", "")
+            .append_pair("cors", "")
+            .append_pair("_", &timestamp);
+        // Without an OAuth token, try the request with the CSRF token only.
+        let oauth_token = self.oauth_token.read().unwrap();
+        if with_oauth && !oauth_token.is_empty() {
+            arguments.append_pair("oauth", &oauth_token);
+        }
+        if with_csrf {
+            arguments.append_pair("csrf", &self.csrf_token.read().unwrap());
+        }
+        arguments.extend_pairs(params);
+        let separator = match query.chars().last() {
+            Some('?') | Some('&') => "",
+            _ if query.contains('?') => "&",
+            _ => "?",
+        };
+        format!("{}/{}{}{}", base, query, separator, arguments.finish())
+    }
+    /// Queries the specified base url with the specified query.
+    /// Returns the raw response body.
+    fn query_body(
+        &self,
+        base: &str,
+        query: &str,
+        with_oauth: bool,
+        with_csrf: bool,
+        params: &[(&str, &str)],
+    ) -> Result<String> {
+        let url = self.query_url(base, query, with_oauth, with_csrf, params);
+        let mut content = Vec::new();
+        let request = match self.deadline {
+            // Requests made before the deadline use a client
+            // timing out once the deadline is reached.
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining == Duration::from_secs(0) {
+                    return Err(InternalSpotifyError::Timeout);
+                }
+                let mut builder = Client::builder().timeout(remaining);
+                if base == self.token_url {
+                    builder = builder.use_sys_proxy();
+                }
+                builder.build()?.get::<&str>(url.as_ref())
             }
-            if with_csrf {
-                arguments.push_str(format!("&csrf={}", self.csrf_token).as_ref());
+            None if base == self.token_url => self.token_client.get::<&str>(url.as_ref()),
+            None => self.client.lock().unwrap().get::<&str>(url.as_ref()),
+        };
+        let resp = match request
+            .header(USER_AGENT, self.user_agent.as_str())
+            .header(
+                ORIGIN,
+                format!("{}://{}", HEADER_ORIGIN_SCHEME, HEADER_ORIGIN_HOST),
+            )
+            .header(REFERER, format!("{}/{}", URL_EMBED, self.referal_track))
+            .send()
+        {
+            Ok(result) => result,
+            Err(ref error) if self.deadline.is_some() && error.is_timeout() => {
+                return Err(InternalSpotifyError::Timeout)
             }
-            if let Some(params) = params {
-                for elem in params {
-                    arguments.push_str(format!("&{}", elem).as_ref());
-                }
+            Err(error) => return Err(error.into()),
+        };
+        // Read at most one byte more than allowed, to tell
+        // a body of exactly the maximum size from a larger one.
+        // The body is only decoded afterwards, since the cut
+        // may fall into the middle of a multi-byte character.
+        let limit = self.max_response_size as u64 + 1;
+        if resp.take(limit).read_to_end(&mut content)? > self.max_response_size {
+            return Err(InternalSpotifyError::ResponseTooLarge);
+        }
+        String::from_utf8(content)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error).into())
+    }
+}
+
+/// Gets the explicitly configured host, if any.
+/// The host takes precedence over the local address,
+/// which takes precedence over the `SPOTIFY_LOCAL_HOST` variable.
+fn explicit_host(config: &ConnectorConfig) -> Option<String> {
+    config
+        .host
+        .clone()
+        .or_else(|| match config.local_addr? {
+            IpAddr::V4(addr) => Some(addr.to_string()),
+            IpAddr::V6(addr) => Some(format!("[{}]", addr)),
+        })
+        .or_else(|| env::var(ENV_LOCAL_HOST).ok())
+}
+
+/// Gets the local hosts to try, in order.
+/// An explicit host is used exclusively.
+fn local_hosts(host: Option<String>) -> Vec<String> {
+    match host {
+        Some(host) => vec![host],
+        None => std::iter::once(URL_LOCAL_HOST)
+            .chain(URL_LOCAL_FALLBACK_HOSTS.iter().cloned())
+            .map(str::to_owned)
+            .collect(),
+    }
+}
+
+/// Tests whether the specified address is already in use.
+/// Other bind errors, e.g. on systems without IPv6, are ignored.
+fn is_in_use(addr: (&str, u16)) -> bool {
+    match TcpListener::bind(addr) {
+        Err(error) => error.kind() == io::ErrorKind::AddrInUse,
+        Ok(_) => false,
+    }
+}
+
+/// Finds the CSRF token in the response of the CSRF endpoint.
+/// Client versions disagree on where the token lives, so all
+/// known key paths are tried in order.
+/// Returns the matching key path together with the token.
+fn find_csrf_token(json: &JsonValue) -> Option<(&'static [&'static str], &str)> {
+    CSRF_TOKEN_PATHS.iter().find_map(|path| {
+        let value = path.iter().fold(json, |json, key| &json[*key]);
+        value.as_str().map(|token| (*path, token))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        explicit_host, find_csrf_token, local_hosts, parse_status_response, ConnectorConfig,
+        InternalSpotifyError, SpotifyConnector,
+    };
+    use crate::test_server::{config_for, spotify_routes, TestServer};
+    use std::net::TcpListener;
+    use std::time::{Duration, Instant};
+
+    fn connect(server: &TestServer) -> SpotifyConnector {
+        SpotifyConnector::connect_new(&config_for(server)).unwrap()
+    }
+
+    #[test]
+    fn connect_new_fetches_tokens() {
+        let server = TestServer::start(spotify_routes());
+        let connector = connect(&server);
+        assert_eq!(&*connector.oauth_token(), "oauth-token");
+        assert_eq!(&*connector.csrf_token(), "csrf-token");
+        assert_eq!(connector.port(), server.port());
+    }
+
+    #[test]
+    fn connect_new_continues_without_optional_oauth_token() {
+        let server = TestServer::start(spotify_routes());
+        let mut config = ConnectorConfig {
+            token_url: Some(format!("{}/missing", server.url())),
+            ..config_for(&server)
+        };
+        assert!(SpotifyConnector::connect_new(&config).is_err());
+        config.optional_oauth = true;
+        let connector = SpotifyConnector::connect_new(&config).unwrap();
+        assert_eq!(&*connector.oauth_token(), "");
+        assert_eq!(&*connector.csrf_token(), "csrf-token");
+        let url = connector.query_url("", "remote/status.json", true, true, &[]);
+        assert!(!url.contains("oauth="));
+        assert!(url.contains("csrf=csrf-token"));
+    }
+
+    #[test]
+    fn refresh_tokens_replaces_stale_tokens() {
+        let server = TestServer::start(spotify_routes());
+        let connector = connect(&server);
+        *connector.oauth_token.write().unwrap() = "stale".into();
+        *connector.csrf_token.write().unwrap() = "stale".into();
+        connector.refresh_tokens().unwrap();
+        assert_eq!(&*connector.oauth_token(), "oauth-token");
+        assert_eq!(&*connector.csrf_token(), "csrf-token");
+    }
+
+    #[test]
+    fn connect_new_fails_if_the_client_is_not_running() {
+        let mut routes = spotify_routes();
+        routes.retain(|route| route.0 != "/remote/open.json");
+        routes.push(("/remote/open.json", r#"{"running": false}"#.to_owned()));
+        let server = TestServer::start(routes);
+        let config = config_for(&server);
+        match SpotifyConnector::connect_new(&config) {
+            Err(InternalSpotifyError::ClientNotRunning) => (),
+            _ => panic!("expected ClientNotRunning"),
+        }
+        let opened = server.requests();
+        let opened = opened
+            .iter()
+            .filter(|target| target.starts_with("/remote/open.json"));
+        assert_eq!(opened.count(), 2);
+    }
+
+    #[test]
+    fn connect_new_retries_the_client_within_the_deadline() {
+        let mut routes = spotify_routes();
+        routes.retain(|route| route.0 != "/remote/open.json");
+        routes.push(("/remote/open.json", r#"{"running": false}"#.to_owned()));
+        let server = TestServer::start(routes);
+        let config = ConnectorConfig {
+            connect_timeout: Some(Duration::from_millis(100)),
+            ..config_for(&server)
+        };
+        let start = Instant::now();
+        match SpotifyConnector::connect_new(&config) {
+            Err(InternalSpotifyError::Timeout) => (),
+            _ => panic!("expected Timeout"),
+        }
+        assert!(start.elapsed() < Duration::from_millis(400));
+    }
+
+    #[test]
+    fn local_hosts_fall_back_to_loopback_addresses() {
+        assert_eq!(
+            local_hosts(None),
+            ["spotifyrs.spotilocal.com", "127.0.0.1", "[::1]"]
+        );
+        assert_eq!(local_hosts(Some("localhost".to_owned())), ["localhost"]);
+    }
+
+    #[test]
+    fn start_spotify_on_any_keeps_the_first_responding_host() {
+        let server = TestServer::start(spotify_routes());
+        let mut connector = connect(&server);
+        let hosts = ["[::1]".to_owned(), "127.0.0.1".to_owned()];
+        assert!(connector.start_spotify_on_any(&hosts).unwrap());
+        assert_eq!(connector.host, "127.0.0.1");
+    }
+
+    #[test]
+    fn explicit_host_formats_the_local_addr() {
+        let config = ConnectorConfig {
+            local_addr: Some("::1".parse().unwrap()),
+            ..ConnectorConfig::default()
+        };
+        assert_eq!(explicit_host(&config).unwrap(), "[::1]");
+        let config = ConnectorConfig {
+            host: Some("localhost".to_owned()),
+            local_addr: Some("192.168.0.2".parse().unwrap()),
+            ..ConnectorConfig::default()
+        };
+        assert_eq!(explicit_host(&config).unwrap(), "localhost");
+    }
+
+    #[test]
+    fn scan_ports_reports_responding_ports() {
+        let server = TestServer::start(spotify_routes());
+        let closed = TcpListener::bind(("127.0.0.1", 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = ConnectorConfig {
+            host: Some("127.0.0.1".to_owned()),
+            ..ConnectorConfig::default()
+        };
+        let scan = SpotifyConnector::scan_ports(&config, server.port()..server.port() + 1);
+        assert_eq!(scan.unwrap(), [(server.port(), true)]);
+        let scan = SpotifyConnector::scan_ports(&config, closed..closed + 1);
+        assert_eq!(scan.unwrap(), [(closed, false)]);
+    }
+
+    #[test]
+    fn find_csrf_token_accepts_known_layouts() {
+        let fixtures = [
+            (r#"{"token": "csrf-token"}"#, &["token"][..]),
+            (r#"{"csrf": "csrf-token"}"#, &["csrf"][..]),
+            (
+                r#"{"csrf": {"token": "csrf-token"}}"#,
+                &["csrf", "token"][..],
+            ),
+            (r#"{"value": "csrf-token"}"#, &["value"][..]),
+        ];
+        for (fixture, expected) in fixtures.iter() {
+            let json = json::parse(fixture).unwrap();
+            assert_eq!(find_csrf_token(&json), Some((*expected, "csrf-token")));
+        }
+    }
+
+    #[test]
+    fn parse_status_response_rejects_non_objects() {
+        assert!(parse_status_response(r#"{"playing": true}"#).is_ok());
+        for body in &["[]", r#""x""#, "1", "null"] {
+            match parse_status_response(body) {
+                Err(InternalSpotifyError::UnexpectedResponse(_)) => (),
+                result => panic!("unexpected result for {}: {:?}", body, result),
             }
-            arguments
+        }
+    }
+
+    #[test]
+    fn find_csrf_token_rejects_unknown_layouts() {
+        for fixture in [r#"{}"#, r#"{"error": "csrf-token"}"#, r#"{"token": 42}"#].iter() {
+            let json = json::parse(fixture).unwrap();
+            assert_eq!(find_csrf_token(&json), None);
+        }
+    }
+
+    #[test]
+    fn connect_new_detects_refused_connections() {
+        let port = TcpListener::bind(("127.0.0.1", 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = ConnectorConfig {
+            host: Some("127.0.0.1".to_owned()),
+            port: Some(port),
+            token_url: Some(format!("http://127.0.0.1:{}/token", port)),
+            ..ConnectorConfig::default()
         };
-        let url = format!("{}/{}{}", base, query, arguments);
-        let response = {
-            let mut content = String::new();
-            let mut resp = match self
-                .client
-                .lock()
-                .unwrap()
-                .get::<&str>(url.as_ref())
-                .header(USER_AGENT, HEADER_UA)
-                .header(
-                    ORIGIN,
-                    format!("{}://{}", HEADER_ORIGIN_SCHEME, HEADER_ORIGIN_HOST),
-                )
-                .header(REFERER, format!("{}/{}", URL_EMBED, REFERAL_TRACK))
-                .send()
-            {
-                Ok(result) => result,
-                Err(error) => return Err(InternalSpotifyError::ReqwestError(error)),
-            };
-            match resp.read_to_string(&mut content) {
-                Ok(_) => content,
-                Err(error) => return Err(InternalSpotifyError::IOError(error)),
+        let error = SpotifyConnector::connect_new(&config).err().unwrap();
+        assert!(error.is_connection_refused());
+        assert!(error.is_transient());
+        assert!(!InternalSpotifyError::InvalidCSRFToken.is_transient());
+    }
+
+    #[test]
+    fn connect_new_honors_the_connect_timeout() {
+        // Accepts connections into the backlog, but never answers.
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = ConnectorConfig {
+            host: Some("127.0.0.1".to_owned()),
+            port: Some(port),
+            token_url: Some(format!("http://127.0.0.1:{}/token", port)),
+            connect_timeout: Some(Duration::from_millis(200)),
+            ..ConnectorConfig::default()
+        };
+        let start = Instant::now();
+        let error = SpotifyConnector::connect_new(&config).err().unwrap();
+        assert!(matches!(error, InternalSpotifyError::Timeout));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn fetch_status_json_sends_tokens() {
+        let server = TestServer::start(spotify_routes());
+        let connector = connect(&server);
+        let json = connector.fetch_status_json().unwrap();
+        assert_eq!(json["client_version"], "1.0.42.151.g19de0aa6");
+        let request = server.requests().pop().unwrap();
+        assert!(request.starts_with("/remote/status.json?"));
+        assert!(request.contains("oauth=oauth-token"));
+        assert!(request.contains("csrf=csrf-token"));
+    }
+
+    #[test]
+    fn fetch_status_json_rejects_non_objects() {
+        for body in [r#"["error"]"#, r#""error""#, "null"].iter() {
+            let mut routes = spotify_routes();
+            routes.retain(|route| route.0 != "/remote/status.json");
+            routes.push(("/remote/status.json", body.to_string()));
+            let server = TestServer::start(routes);
+            let connector = connect(&server);
+            match connector.fetch_status_json() {
+                Err(InternalSpotifyError::UnexpectedResponse(json)) => assert!(!json.is_object()),
+                result => panic!("unexpected result: {:?}", result),
             }
+        }
+    }
+
+    #[test]
+    fn fetch_status_json_limits_the_response_size() {
+        let mut routes = spotify_routes();
+        routes.retain(|route| route.0 != "/remote/status.json");
+        routes.push(("/remote/status.json", format!("[{}0]", "0,".repeat(1024))));
+        let server = TestServer::start(routes);
+        let config = ConnectorConfig {
+            max_response_size: Some(1024),
+            ..config_for(&server)
+        };
+        let connector = SpotifyConnector::connect_new(&config).unwrap();
+        match connector.fetch_status_json() {
+            Err(InternalSpotifyError::ResponseTooLarge) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn fetch_status_json_limits_the_response_size_within_a_character() {
+        // The two-byte character starts right at the limit.
+        let body = format!("[\"{}\u{e9}\"]", "a".repeat(1022));
+        let mut routes = spotify_routes();
+        routes.retain(|route| route.0 != "/remote/status.json");
+        routes.push(("/remote/status.json", body));
+        let server = TestServer::start(routes);
+        let config = ConnectorConfig {
+            max_response_size: Some(1024),
+            ..config_for(&server)
         };
-        match json::parse(response.as_ref()) {
-            Ok(result) => Ok(result),
-            Err(error) => Err(InternalSpotifyError::JSONParseError(error)),
+        let connector = SpotifyConnector::connect_new(&config).unwrap();
+        match connector.fetch_status_json() {
+            Err(InternalSpotifyError::ResponseTooLarge) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn request_play_sends_uri() {
+        let server = TestServer::start(spotify_routes());
+        let connector = connect(&server);
+        assert!(connector.request_play("spotify:track:4uLU6hMCjMI75M1A2tKUQC".to_owned()));
+        let request = server.requests().pop().unwrap();
+        assert!(request.starts_with("/remote/play.json?"));
+        assert!(request.contains("uri=spotify%3Atrack%3A4uLU6hMCjMI75M1A2tKUQC"));
+    }
+
+    #[test]
+    fn request_pause_retries_without_effect() {
+        // The canned status is always playing, so pausing has no effect.
+        let server = TestServer::start(spotify_routes());
+        let connector = connect(&server);
+        assert!(connector.request_pause(true));
+        let requests = server.requests();
+        let pauses: Vec<_> = requests
+            .iter()
+            .filter(|request| request.starts_with("/remote/pause.json?"))
+            .collect();
+        assert_eq!(pauses.len(), 2);
+        assert!(pauses[0].contains("pause=true"));
+        assert!(pauses[1].contains("pause=1"));
+        assert!(connector.request_pause(false));
+        let request = server.requests().pop().unwrap();
+        assert!(request.contains("pause=false"));
+    }
+
+    #[test]
+    fn query_url_is_well_formed() {
+        let server = TestServer::start(spotify_routes());
+        let connector = connect(&server);
+        let base = connector.get_local_url();
+        let url = connector.query_url(&base, "remote/play.json", true, true, &[("uri", "a b#c")]);
+        assert!(!url.contains("?&"));
+        assert!(url.starts_with(&format!("{}/remote/play.json?ref=&cors=&_=", base)));
+        assert!(url.ends_with("&oauth=oauth-token&csrf=csrf-token&uri=a+b%23c"));
+        let url = connector.query_url(&base, "remote/play.json?x=1", false, false, &[]);
+        assert!(url.starts_with(&format!("{}/remote/play.json?x=1&ref=", base)));
+        for request in server.requests() {
+            assert!(!request.contains("?&"));
         }
     }
 }
@@ -1,4 +1,8 @@
 #![warn(missing_docs)]
+#![allow(clippy::needless_doctest_main)]
+// The Windows process module is the only one allowed to use `unsafe`.
+#![cfg_attr(not(all(windows, feature = "windows-process")), forbid(unsafe_code))]
+#![cfg_attr(all(windows, feature = "windows-process"), deny(unsafe_code))]
 //! The Spotify crate.
 //!
 //! This crate contains methods to retrieve information from
@@ -16,7 +20,9 @@
 //!
 //!   And a few goodies:
 //!   - Automatically fixes broken (but recoverable) track IDs and URIs
-//!   - Check whether SpotifyWebHelper is running (Windows only)
+//!   - Check whether SpotifyWebHelper is running (Windows only, `windows-process` feature, on by default)
+//!   - Serve the playback status as an MPRIS media player (Linux only, `mpris` feature)
+//!   - Consume status updates as a `futures::Stream` (`async` feature)
 //!
 //   ## Is the project still alive?
 //
@@ -77,6 +83,31 @@
 //!                       println!("Internal Error: {:?}", err);
 //!                       std::process::exit(3);
 //!                   }
+//!                   SpotifyError::Timeout => {
+//!                       println!("Timed out while waiting for Spotify!");
+//!                       std::process::exit(5);
+//!                   }
+//!                   SpotifyError::NothingPlaying => {
+//!                       println!("Nothing is playing!");
+//!                       std::process::exit(6);
+//!                   }
+//!                   SpotifyError::TrackChanged => {
+//!                       println!("The track changed unexpectedly!");
+//!                       std::process::exit(7);
+//!                   }
+//!                   SpotifyError::Unsupported(capability) => {
+//!                       println!("Unsupported by the Spotify Client: {:?}", capability);
+//!                       std::process::exit(8);
+//!                   }
+//!                   error @ SpotifyError::ConnectFailed(_) => {
+//!                       println!("Error: {}", error);
+//!                       std::process::exit(9);
+//!                   }
+//!                   // Variants added in later versions.
+//!                   error => {
+//!                       println!("Error: {}", error);
+//!                       std::process::exit(10);
+//!                   }
 //!               }
 //!           }
 //!       };
@@ -109,8 +140,8 @@
 //!
 //!       // Join the reactor thread so the application
 //!       // doesn't close before receiving any data.
-//!       if reactor.join().ok().is_none() {
-//!           println!("Unable to join into the live-update.");
+//!       if reactor.ok().and_then(|reactor| reactor.join().ok()).is_none() {
+//!           println!("Unable to start or join into the live-update.");
 //!           std::process::exit(4);
 //!       }
 //!   }
@@ -145,32 +176,86 @@
 //!   You might wanna restart Spotify after doing that.
 //!
 //!   > **Update**: I'm not sure if this option is still exposed nowadays. Spotify 1.1.95 (2022) on macOS doesn't seem to have this anymore, and I'm not sure if Spotify still exposes the local API at all. If it doesn't, this library is pretty much useless. If you know whether this still works, please open an issue and let me know!
+//!
+//!   **Does it work behind a proxy?**
+//!   The OAuth token is fetched from `open.spotify.com` using the proxy from the `HTTP_PROXY` and `HTTPS_PROXY` environment variables.
+//!   Requests to the local Spotify server always bypass the proxy, so there is no need to add `spotilocal.com` to `NO_PROXY`.
+//!
+//!   **What if `spotilocal.com` does not resolve?**
+//!   Unless a host is set explicitly, the local server is looked up at `spotifyrs.spotilocal.com`, then at `127.0.0.1` and finally at `[::1]`.
+//!   The first host that responds is used for all further requests.
 
 // Extern crates
+#[cfg(feature = "async")]
+extern crate futures;
 extern crate json;
+#[macro_use]
+extern crate log;
 extern crate reqwest;
 extern crate time;
+extern crate url;
+#[cfg(all(windows, feature = "windows-process"))]
 extern crate winapi;
+#[cfg(all(target_os = "linux", feature = "mpris"))]
+extern crate zbus;
 
 // Modules
+mod builder;
 mod connector;
+#[cfg(all(target_os = "linux", feature = "mpris"))]
+mod mpris;
+mod poll;
+pub mod processes;
 pub mod status;
-#[cfg(windows)]
+#[cfg(feature = "async")]
+mod stream;
+#[cfg(test)]
+mod test_server;
+mod uri;
+#[cfg(all(windows, feature = "windows-process"))]
 mod windows_process;
 
 // Imports
-use crate::connector::{InternalSpotifyError, SpotifyConnector};
-use crate::status::{SpotifyStatus, SpotifyStatusChange};
-use std::thread::{self, JoinHandle};
-use std::time::Duration;
-#[cfg(windows)]
+pub use crate::builder::SpotifyBuilder;
+use crate::connector::{
+    parse_status_response, ConnectorConfig, InternalSpotifyError, SpotifyConnector,
+};
+use crate::poll::PollCounters;
+pub use crate::poll::{PollHandle, PollOptions, PollStats};
+use crate::status::{
+    PlaybackState, SimpleTrack, SpotifyStatus, SpotifyStatusChange, StatusDiagnostic,
+};
+use crate::uri::normalize_uri;
+pub use crate::uri::{ParseTrackUriError, TrackUri};
+use json::JsonValue;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(unix)]
+use std::process::Command;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+#[cfg(all(windows, feature = "windows-process"))]
 use windows_process::WindowsProcess;
 
+// Tracks played to within this many seconds of their end count as ended
+const TRACK_END_MARGIN: f32 = 2_f32;
+// Volumes within this distance count as equal
+const VOLUME_EPSILON: f32 = 0.005;
+
 /// The `Result` type used in this crate.
 type Result<T> = std::result::Result<T, SpotifyError>;
 
 /// The `SpotifyError` enum.
+///
+/// New variants may be added in minor releases,
+/// so matches on it need a catch-all arm.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum SpotifyError {
     /// An internal error.
     InternalError(InternalSpotifyError),
@@ -178,132 +263,1792 @@ pub enum SpotifyError {
     ClientNotRunning,
     /// Indicates that the SpotifyWebHelper process it not running.
     WebHelperNotRunning,
+    /// Indicates that an operation did not complete in time.
+    Timeout,
+    /// Indicates that no track is loaded in the client.
+    NothingPlaying,
+    /// Indicates that the track changed while waiting on it.
+    TrackChanged,
+    /// Indicates that the client does not support an operation.
+    Unsupported(Capability),
+    /// Indicates that every attempt to connect failed.
+    /// Lists each attempt together with its error.
+    ConnectFailed(Vec<(String, SpotifyError)>),
+    /// Indicates that a track uri is invalid.
+    InvalidTrackUri(ParseTrackUriError),
+}
+
+/// Implements `From<InternalSpotifyError>` for `SpotifyError`.
+impl From<InternalSpotifyError> for SpotifyError {
+    fn from(error: InternalSpotifyError) -> SpotifyError {
+        SpotifyError::InternalError(error)
+    }
+}
+
+/// Implements `From<ParseTrackUriError>` for `SpotifyError`.
+impl From<ParseTrackUriError> for SpotifyError {
+    fn from(error: ParseTrackUriError) -> SpotifyError {
+        SpotifyError::InvalidTrackUri(error)
+    }
+}
+
+/// Implements `fmt::Display` for `SpotifyError`.
+///
+/// Connection failures include a hint on how to resolve them.
+impl fmt::Display for SpotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpotifyError::InternalError(error) if error.is_connection_refused() => write!(
+                f,
+                "{} (the local Spotify API refused the connection; \
+                 it may be disabled or unavailable in this client version)",
+                error
+            ),
+            SpotifyError::InternalError(error) => write!(f, "internal error: {}", error),
+            SpotifyError::ClientNotRunning => {
+                f.write_str("the Spotify client is not running (launch Spotify and try again)")
+            }
+            SpotifyError::WebHelperNotRunning => f.write_str(
+                "the SpotifyWebHelper process is not running (enable \"Allow Spotify \
+                 to be opened from the web\" in the advanced settings of the client)",
+            ),
+            SpotifyError::Timeout => f.write_str("timed out while waiting for Spotify"),
+            SpotifyError::NothingPlaying => f.write_str("no track is loaded in the client"),
+            SpotifyError::TrackChanged => f.write_str("the track changed while waiting on it"),
+            SpotifyError::Unsupported(capability) => {
+                write!(f, "the Spotify client does not support {:?}", capability)
+            }
+            SpotifyError::ConnectFailed(attempts) => {
+                f.write_str("unable to connect to Spotify")?;
+                for (attempt, error) in attempts {
+                    write!(f, "; {}: {}", attempt, error)?;
+                }
+                Ok(())
+            }
+            SpotifyError::InvalidTrackUri(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Implements `Error` for `SpotifyError`.
+impl Error for SpotifyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SpotifyError::InternalError(error) => Some(error),
+            SpotifyError::InvalidTrackUri(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// The state of the connection to the local Spotify client.
+///
+/// While polling, a lost connection is re-established automatically,
+/// which is reflected in the state.
+#[derive(Debug, Clone)]
+pub enum ConnectionState {
+    /// The connection is lost. Reconnecting starts with the next poll.
+    Disconnected,
+    /// The handshake with the client is in progress,
+    /// either initially or while reconnecting.
+    Connecting,
+    /// The connection is established.
+    Connected,
+    /// The connection is being re-established.
+    /// The handshake starts with the next poll.
+    Reconnecting,
+    /// The last attempt to re-establish the connection failed.
+    /// The handshake is retried with the next poll.
+    Failed(Arc<SpotifyError>),
+}
+
+/// A snapshot of how the connection to the local Spotify client
+/// was established. The tokens themselves are not exposed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// The port of the local server.
+    pub port: u16,
+    /// The url of the local server.
+    pub local_url: String,
+    /// Whether an OAuth token was fetched.
+    pub oauth_present: bool,
+    /// Whether a CSRF token was fetched.
+    pub csrf_present: bool,
+    /// The scheme of the local server (`http` or `https`).
+    pub scheme: &'static str,
+}
+
+/// A desired state of the client, to be reached by `Spotify::set_state`.
+/// Fields left as `None` are not changed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DesiredState {
+    /// Whether a track is playing.
+    pub playing: Option<bool>,
+    /// The volume, from `0.0` to `1.0`.
+    pub volume: Option<f32>,
+    /// Whether shuffle mode is enabled.
+    pub shuffle: Option<bool>,
+    /// The track uri or id to play.
+    pub track: Option<String>,
+}
+
+/// A capability the local Spotify client may or may not support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// Changing the volume.
+    Volume,
+    /// Seeking within the current track.
+    Seek,
+    /// Toggling shuffle mode.
+    Shuffle,
+    /// Toggling repeat mode.
+    Repeat,
+    /// Skipping to the next track.
+    Next,
+    /// Skipping to the previous track.
+    Prev,
+    /// Toggling the private session.
+    PrivateSession,
+    /// Queueing tracks.
+    Queue,
 }
 
 /// The Spotify API.
 pub struct Spotify {
     /// The Spotify connector.
-    connector: SpotifyConnector,
+    /// Shared with the keepalive thread, if enabled.
+    connector: Arc<SpotifyConnector>,
+    /// The configuration used to (re)connect.
+    config: ConnectorConfig,
+    /// The connection state.
+    state: Arc<Mutex<ConnectionState>>,
+    /// The hash of the last response body fetched by `status_cached`,
+    /// together with the status parsed from it.
+    status_cache: Mutex<Option<(u64, SpotifyStatus)>>,
+    /// The volume remembered by `mute`, or `None` if not muted.
+    muted_volume: Mutex<Option<f32>>,
 }
 
 /// Fetches the current status from Spotify.
 fn get_status(connector: &SpotifyConnector) -> Result<SpotifyStatus> {
-    match connector.fetch_status_json() {
-        Ok(result) => Ok(SpotifyStatus::from(result)),
-        Err(error) => Err(SpotifyError::InternalError(error)),
+    Ok(SpotifyStatus::from(connector.fetch_status_json()?))
+}
+
+/// Spawns a thread issuing a keepalive request through the specified
+/// connector at the specified interval, which refreshes the tokens
+/// if the request fails. The thread ends once the connector is dropped.
+fn spawn_keepalive(connector: &Arc<SpotifyConnector>, interval: Duration) {
+    let connector: Weak<SpotifyConnector> = Arc::downgrade(connector);
+    let result = thread::Builder::new()
+        .name("spotify-keepalive".to_owned())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            let connector = match connector.upgrade() {
+                Some(connector) => connector,
+                None => return,
+            };
+            if let Err(error) = connector.keep_alive() {
+                warn!("Unable to keep the connection alive: {}", error);
+            }
+        });
+    if let Err(error) = result {
+        warn!("Unable to spawn the keepalive thread: {}", error);
     }
 }
 
+/// Tests whether the track of the last status ended naturally,
+/// i.e. it was within `TRACK_END_MARGIN` of its end and then
+/// either changed or started over, e.g. on repeat.
+fn track_ended(last: &SpotifyStatus, curr: &SpotifyStatus) -> bool {
+    let track = last.track_ref();
+    let length = track.length_secs() as f32;
+    if length == 0_f32 || track.track.uri.is_empty() {
+        return false;
+    }
+    let position = last.playing_position();
+    position >= length - TRACK_END_MARGIN
+        && (!curr.track_ref().same_song_as(track) || curr.playing_position() < position)
+}
+
+/// Applies the specified volume, given the current one. The local API
+/// has no end-point to change the volume, so this only succeeds if the
+/// client already plays at about the specified volume.
+fn apply_volume(current: f32, desired: f32) -> Result<()> {
+    if (current - desired).abs() > VOLUME_EPSILON {
+        return Err(SpotifyError::Unsupported(Capability::Volume));
+    }
+    Ok(())
+}
+
+/// Applies the specified mode, given the current one. The local API
+/// has no end-point to change the shuffle or repeat mode, so this only
+/// succeeds if the mode is already set as specified.
+fn apply_mode(current: bool, desired: bool, capability: Capability) -> Result<()> {
+    if current != desired {
+        return Err(SpotifyError::Unsupported(capability));
+    }
+    Ok(())
+}
+
+/// Connects to the local Spotify client, fetches
+/// the current status once and drops the connection.
+///
+/// This is a shorthand for `Spotify::connect()?.status()`,
+/// meant for scripts that only want to know what's playing.
+pub fn current_status() -> Result<SpotifyStatus> {
+    Spotify::connect()?.status()
+}
+
 /// Implements `Spotify`.
 impl Spotify {
     /// Connects to the local Spotify client.
-    #[cfg(windows)]
+    ///
+    /// Connecting opens (and possibly starts) the client. The local API
+    /// offers no way to do so in the background, so the client window
+    /// may be brought to the foreground.
     pub fn connect() -> Result<Spotify> {
-        // TODO:
-        // At some point, the connector should automatically
-        // open Spotify in the case  that Spotify is closed.
-        // That would also be a much better cross-platform solution,
-        // because it would work on Linux and macOS and make
-        // the dependency on winapi and kernel32-sys unnecessary.
-        if !Spotify::spotify_webhelper_alive() {
-            return Err(SpotifyError::WebHelperNotRunning);
+        Spotify::builder().connect()
+    }
+    /// Connects to the local Spotify client on a dedicated thread,
+    /// without blocking the calling task.
+    /// See `SpotifyBuilder::connect_offloaded`.
+    ///
+    /// Only available with the `async` feature enabled.
+    #[cfg(feature = "async")]
+    pub fn connect_offloaded() -> impl std::future::Future<Output = Result<Spotify>> {
+        Spotify::builder().connect_offloaded()
+    }
+    /// Connects to the local Spotify client, giving up with
+    /// `SpotifyError::Timeout` if the whole connect sequence
+    /// (port detection, opening the client and fetching the
+    /// tokens) takes longer than the specified timeout.
+    pub fn connect_with_deadline(timeout: Duration) -> Result<Spotify> {
+        Spotify::builder().connect_timeout(timeout).connect()
+    }
+    /// Probes each port the local server may listen on for a CSRF token,
+    /// without connecting. Returns each port and whether it responded,
+    /// which helps to diagnose port conflicts.
+    pub fn scan_ports() -> Vec<(u16, bool)> {
+        let ports = SpotifyConnector::port_range();
+        match SpotifyConnector::scan_ports(&ConnectorConfig::default(), ports.clone()) {
+            Ok(result) => result,
+            Err(_) => ports.map(|port| (port, false)).collect(),
         }
-        Spotify::new_unchecked()
     }
-    /// Connects to the local Spotify client.
-    #[cfg(not(windows))]
-    pub fn connect() -> Result<Spotify> {
-        Spotify::new_unchecked()
+    /// Connects to the local Spotify client, trying the local server
+    /// over plain HTTP first and over HTTPS second. Returns the first
+    /// connection the status can be fetched from.
+    ///
+    /// Each attempt attaches to a running client, or starts it if needed.
+    /// Fails with `SpotifyError::ConnectFailed` listing the error of
+    /// every attempt if none works.
+    pub fn connect_first_available() -> Result<Spotify> {
+        let mut attempts = Vec::new();
+        for &(attempt, https) in &[("http", false), ("https", true)] {
+            let result = Spotify::builder()
+                .https(https)
+                .connect()
+                .and_then(|spotify| {
+                    spotify.status()?;
+                    Ok(spotify)
+                });
+            match result {
+                Ok(spotify) => return Ok(spotify),
+                Err(error) => attempts.push((attempt.to_owned(), error)),
+            }
+        }
+        Err(SpotifyError::ConnectFailed(attempts))
+    }
+    /// Constructs a new `SpotifyBuilder` to configure the connection.
+    pub fn builder() -> SpotifyBuilder {
+        SpotifyBuilder::new()
     }
     /// Constructs a new `self::Result<Spotify>`.
-    fn new_unchecked() -> Result<Spotify> {
-        match SpotifyConnector::connect_new() {
-            Ok(result) => Ok(Spotify { connector: result }),
-            Err(error) => Err(SpotifyError::InternalError(error)),
+    fn new_unchecked(config: &ConnectorConfig) -> Result<Spotify> {
+        let state = Arc::new(Mutex::new(ConnectionState::Disconnected));
+        let connector = Spotify::handshake(config, &state)?;
+        Ok(Spotify {
+            connector,
+            config: config.clone(),
+            state,
+            status_cache: Mutex::new(None),
+            muted_volume: Mutex::new(None),
+        })
+    }
+    /// Connects to the client using the specified configuration.
+    /// Marks the specified state as connecting during the handshake,
+    /// and as connected once it succeeded.
+    fn handshake(
+        config: &ConnectorConfig,
+        state: &Mutex<ConnectionState>,
+    ) -> Result<Arc<SpotifyConnector>> {
+        *state.lock().unwrap() = ConnectionState::Connecting;
+        let connector = match SpotifyConnector::connect_new(config) {
+            Ok(connector) => Arc::new(connector),
+            Err(InternalSpotifyError::Timeout) => return Err(SpotifyError::Timeout),
+            Err(InternalSpotifyError::ClientNotRunning) => {
+                return Err(SpotifyError::ClientNotRunning)
+            }
+            Err(error) => return Err(SpotifyError::InternalError(error)),
+        };
+        if let Some(interval) = config.keepalive {
+            spawn_keepalive(&connector, interval);
+        }
+        *state.lock().unwrap() = ConnectionState::Connected;
+        Ok(connector)
+    }
+    /// Constructs a new `Spotify` sharing the connector, and thereby
+    /// the tokens and the keepalive thread, as well as the connection
+    /// state of this one.
+    fn share(&self) -> Spotify {
+        Spotify {
+            connector: self.connector.clone(),
+            config: self.config.clone(),
+            state: self.state.clone(),
+            status_cache: Mutex::new(None),
+            muted_volume: Mutex::new(None),
         }
     }
     /// Moves `self` to a new thread and begins polling the
     /// client status. Sends the updated status to the specified
     /// closure, together with information of which fields had changed
-    /// since the last update. Returns the `JoinHandle` of the new thread.
-    pub fn poll<F: 'static>(self, f: F) -> JoinHandle<()>
+    /// since the last update. Returns the `PollHandle` of the new thread,
+    /// which is named `spotify-poll`, or an error if it couldn't be spawned.
+    ///
+    /// The closure may mutate the state it captures, e.g. to collect
+    /// a play history, since it is only ever called from that thread.
+    pub fn poll<F>(self, f: F) -> Result<PollHandle>
     where
-        F: Fn(&Spotify, SpotifyStatus, SpotifyStatusChange) -> bool,
-        F: std::marker::Send,
+        F: FnMut(&Spotify, SpotifyStatus, SpotifyStatusChange) -> bool,
+        F: std::marker::Send + 'static,
     {
-        thread::spawn(move || {
-            let sleep_time = Duration::from_millis(250);
-            let mut last: Option<SpotifyStatus> = None;
-            let mut curr: Option<SpotifyStatus>;
-            let mut first = true;
-            loop {
-                curr = get_status(&self.connector).ok();
-                {
-                    let last = last.clone();
-                    if first && curr.is_some() {
-                        let curr = curr.clone().unwrap();
-                        if !f(&self, curr.clone(), SpotifyStatusChange::new_true()) {
-                            break;
-                        }
-                        first = false;
-                    } else if !first && curr.is_some() && last.is_some() {
-                        let curr = curr.clone().unwrap();
-                        let last = last.unwrap();
-                        if !f(&self, curr.clone(), SpotifyStatusChange::from((curr, last))) {
-                            break;
+        self.poll_with(PollOptions::new(), f)
+    }
+    /// Moves `self` to a new thread and begins polling the
+    /// client status using the specified options, like `poll`.
+    pub fn poll_with<F>(mut self, options: PollOptions, mut f: F) -> Result<PollHandle>
+    where
+        F: FnMut(&Spotify, SpotifyStatus, SpotifyStatusChange) -> bool,
+        F: std::marker::Send + 'static,
+    {
+        let counters = PollCounters::new(options.history_capacity);
+        PollHandle::spawn(self.state.clone(), counters, move |counters| {
+            self.run_poll(&options, &mut f, counters)
+        })
+        .map_err(|error| InternalSpotifyError::from(error).into())
+    }
+    /// Moves `self` to a new thread and begins polling the client
+    /// status in the specified interval. Calls the specified closure
+    /// with the new track whenever the track URI changes.
+    ///
+    /// Returning false from the closure stops polling.
+    pub fn on_track_change<F>(self, interval: Duration, mut f: F) -> Result<PollHandle>
+    where
+        F: FnMut(&SimpleTrack) -> bool,
+        F: std::marker::Send + 'static,
+    {
+        let mut last_uri = String::new();
+        self.poll_with(
+            PollOptions::new().interval(interval),
+            move |_, status, _| {
+                let uri = status.full_track().track.uri;
+                if uri.is_empty() || last_uri == uri {
+                    return true;
+                }
+                last_uri = uri;
+                f(&status.track())
+            },
+        )
+    }
+    /// Moves `self` to a new thread and begins polling the client
+    /// status. Calls the specified closure with the ended track
+    /// whenever a track plays to its end, unlike a skipped one.
+    ///
+    /// A track counts as ended if it was last seen within two seconds
+    /// of its end, and then changed or started over. Tracks skipped
+    /// within the last two seconds are therefore reported as well.
+    ///
+    /// Returning false from the closure stops polling.
+    pub fn on_track_end<F>(self, mut f: F) -> Result<PollHandle>
+    where
+        F: FnMut(&SimpleTrack) -> bool,
+        F: std::marker::Send + 'static,
+    {
+        let mut last: Option<SpotifyStatus> = None;
+        self.poll_with(PollOptions::new(), move |_, status, _| {
+            let ended = last.take().filter(|last| track_ended(last, &status));
+            last = Some(status);
+            match ended {
+                Some(ended) => f(&ended.track()),
+                None => true,
+            }
+        })
+    }
+    /// Moves `self` to a new thread and begins polling the
+    /// client status, like `poll`. If the closure panics, the panic
+    /// is caught and logged, and polling is restarted with a fresh
+    /// status, as if `poll` had just been called.
+    ///
+    /// Polling is restarted at most `max_restarts` times. The next
+    /// panic after that is propagated, and `PollHandle::join`
+    /// returns it as an error.
+    pub fn poll_supervised<F>(self, max_restarts: usize, f: F) -> Result<PollHandle>
+    where
+        F: FnMut(&Spotify, SpotifyStatus, SpotifyStatusChange) -> bool,
+        F: std::marker::Send + 'static,
+    {
+        self.poll_supervised_with(PollOptions::new(), max_restarts, f)
+    }
+    /// Moves `self` to a new thread and begins polling the client
+    /// status using the specified options, like `poll_supervised`.
+    ///
+    /// The options apply to every restart as well, and the statistics
+    /// of the returned `PollHandle` accumulate across restarts.
+    pub fn poll_supervised_with<F>(
+        mut self,
+        options: PollOptions,
+        max_restarts: usize,
+        mut f: F,
+    ) -> Result<PollHandle>
+    where
+        F: FnMut(&Spotify, SpotifyStatus, SpotifyStatusChange) -> bool,
+        F: std::marker::Send + 'static,
+    {
+        PollHandle::spawn(
+            self.state.clone(),
+            PollCounters::new(options.history_capacity),
+            move |counters| {
+                let mut restarts = 0;
+                loop {
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                        self.run_poll(&options, &mut f, counters)
+                    }));
+                    match result {
+                        Ok(()) => break,
+                        Err(payload) if restarts < max_restarts => {
+                            restarts += 1;
+                            let message = payload
+                                .downcast_ref::<&str>()
+                                .copied()
+                                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                                .unwrap_or("unknown panic");
+                            error!(
+                                "Poll callback panicked ({}), restarting ({}/{})",
+                                message, restarts, max_restarts
+                            );
                         }
+                        Err(payload) => panic::resume_unwind(payload),
                     }
                 }
-                if curr.is_some() {
-                    last = curr.clone();
+            },
+        )
+        .map_err(|error| InternalSpotifyError::from(error).into())
+    }
+    /// Polls the client status using the specified options until
+    /// the specified closure returns false or polling is stopped.
+    /// Records each iteration in the specified counters.
+    ///
+    /// If fetching the status fails, the connection is considered lost
+    /// and is re-established on the following iterations. Each
+    /// successful fetch marks the connection as established.
+    fn run_poll<F>(&mut self, options: &PollOptions, f: &mut F, counters: &PollCounters)
+    where
+        F: FnMut(&Spotify, SpotifyStatus, SpotifyStatusChange) -> bool,
+    {
+        let mut last: Option<SpotifyStatus> = None;
+        let mut curr: Option<SpotifyStatus>;
+        let mut first = true;
+        while !counters.is_stopped() {
+            counters.tick();
+            curr = match get_status(&self.connector) {
+                Ok(status) => {
+                    counters.success(&status);
+                    self.set_connection_state(ConnectionState::Connected);
+                    Some(status)
+                }
+                Err(_) => {
+                    counters.failure();
+                    self.reconnect();
+                    None
+                }
+            };
+            {
+                let last = last.clone();
+                if first && curr.is_some() {
+                    let curr = curr.clone().unwrap();
+                    if !options.suppress_initial
+                        && !f(self, curr.clone(), SpotifyStatusChange::new_true())
+                    {
+                        break;
+                    }
+                    first = false;
+                } else if !first && curr.is_some() && last.is_some() {
+                    let curr = curr.clone().unwrap();
+                    let last = last.unwrap();
+                    let change = SpotifyStatusChange::between(&curr, &last);
+                    if !f(self, curr, change) {
+                        break;
+                    }
                 }
-                thread::sleep(sleep_time);
             }
-        })
+            if curr.is_some() {
+                last = curr.clone();
+            }
+            thread::sleep(options.interval);
+        }
+    }
+    /// Advances the connection state after a failed request.
+    /// A connected client is marked as disconnected first, then as
+    /// reconnecting, and on the following calls the handshake is
+    /// performed until it succeeds.
+    fn reconnect(&mut self) {
+        match self.state() {
+            ConnectionState::Connected => self.set_connection_state(ConnectionState::Disconnected),
+            ConnectionState::Disconnected => {
+                self.set_connection_state(ConnectionState::Reconnecting)
+            }
+            ConnectionState::Connecting
+            | ConnectionState::Reconnecting
+            | ConnectionState::Failed(_) => match Spotify::handshake(&self.config, &self.state) {
+                Ok(connector) => self.connector = connector,
+                Err(error) => self.set_connection_state(ConnectionState::Failed(Arc::new(error))),
+            },
+        }
+    }
+    /// Sets the connection state.
+    fn set_connection_state(&self, state: ConnectionState) {
+        *self.state.lock().unwrap() = state;
+    }
+    /// Gets the connection state.
+    pub fn state(&self) -> ConnectionState {
+        self.state.lock().unwrap().clone()
+    }
+    /// Gets information about the connection, e.g. the port and
+    /// scheme in use, without exposing the tokens.
+    pub fn connection_info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            port: self.connector.port(),
+            local_url: self.connector.get_local_url(),
+            oauth_present: !self.connector.oauth_token().is_empty(),
+            csrf_present: !self.connector.csrf_token().is_empty(),
+            scheme: self.connector.scheme(),
+        }
     }
     /// Fetches the current status from the client.
     pub fn status(&self) -> Result<SpotifyStatus> {
         get_status(&self.connector)
     }
-    /// Plays a track.
-    pub fn play(&self, track: String) -> bool {
-        // Try to fix broken track URIs
-        // In: https://open.spotify.com/track/1pGZIV8olkbRMjyHWoEXyt
-        // In: open.spotify.com/track/1pGZIV8olkbRMjyHWoEXyt
-        // In: track/1pGZIV8olkbRMjyHWoEXyt
-        // In: track:1pGZIV8olkbRMjyHWoEXyt
-        // Out: spotify:track:1pGZIV8olkbRMjyHWoEXyt
-        let track: String = {
-            let track = track
-                .replace("https://", "http://") // https -> http
-                .trim_start_matches("http://") // get rid of protocol
-                .trim_start_matches("open.spotify.com") // get rid of domain name
-                .replace('/', ":") // turn all / into :
-                .trim_start_matches(':') // get rid of : at the beginning
-                .to_owned();
-            if track.starts_with("spotify:") {
-                track
-            } else {
-                format!("spotify:{}", track) // prepend proper protocol
+    /// Fetches the uri of the current track, e.g. `spotify:track:<id>`,
+    /// or `None` if no track is loaded in the client.
+    ///
+    /// A lightweight alternative to `status` for scripts only
+    /// interested in the identifier of the track.
+    pub fn current_uri(&self) -> Result<Option<String>> {
+        let uri = self.status()?.track_ref().track.uri.clone();
+        Ok(Some(uri).filter(|uri| !uri.is_empty()))
+    }
+    /// Fetches the current status from the client, like `status`,
+    /// retrying up to `attempts` times in total on transient errors.
+    /// The delay between attempts starts at `backoff` and doubles
+    /// with each retry. Fails with the last error if no attempt succeeds.
+    ///
+    /// Failed or timed out requests and unreadable or malformed
+    /// responses are retried. Other errors, such as an invalid
+    /// CSRF or OAuth token, are returned right away.
+    pub fn status_with_retry(&self, attempts: u32, backoff: Duration) -> Result<SpotifyStatus> {
+        let mut delay = backoff;
+        let mut attempt = 1;
+        loop {
+            match self.status() {
+                Err(SpotifyError::InternalError(ref error))
+                    if attempt < attempts && error.is_transient() =>
+                {
+                    debug!("Fetching the status failed, retrying: {}", error);
+                    thread::sleep(delay);
+                    delay = delay.saturating_mul(2);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+    /// Fetches the current volume, from `0.0` to `1.0`.
+    ///
+    /// The local API has no lighter end-point to read the volume,
+    /// so this fetches the status, e.g. for a volume slider.
+    pub fn volume(&self) -> Result<f32> {
+        Ok(self.status()?.volume())
+    }
+    /// Fetches the current status from the client, like `status`.
+    ///
+    /// If the response is byte-identical to the last one fetched by
+    /// this method, the cached status is returned instead of parsing
+    /// the response again. Since the server time changes every second,
+    /// this only pays off when polling more often than that.
+    pub fn status_cached(&self) -> Result<SpotifyStatus> {
+        let (body, _) = self.connector.fetch_status_json_timed()?;
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+        let hash = hasher.finish();
+        let mut cache = self.status_cache.lock().unwrap();
+        if let Some((last_hash, ref status)) = *cache {
+            if last_hash == hash {
+                return Ok(status.clone());
             }
+        }
+        let status = SpotifyStatus::from(parse_status_response(&body)?);
+        *cache = Some((hash, status.clone()));
+        Ok(status)
+    }
+    /// Fetches the current playing position in seconds, corrected
+    /// for the time elapsed since the client reported it.
+    /// See `SpotifyStatus::estimated_position`.
+    pub fn current_position(&self) -> Result<f32> {
+        Ok(self.status()?.estimated_position())
+    }
+    /// Fetches the current status from the client, together
+    /// with the raw response and timing information.
+    /// Useful for debugging slow or wrong status reports.
+    pub fn status_diagnostic(&self) -> Result<StatusDiagnostic> {
+        let (raw_json, http_latency) = self.connector.fetch_status_json_timed()?;
+        let start = Instant::now();
+        let status = SpotifyStatus::from(parse_status_response(&raw_json)?);
+        Ok(StatusDiagnostic {
+            raw_json,
+            http_latency,
+            parse_time: start.elapsed(),
+            status,
+        })
+    }
+    /// Polls the client status in the specified interval until
+    /// a user-visible change occurs, and returns the new status
+    /// together with the changes since this method was called.
+    ///
+    /// Returns `SpotifyError::Timeout` if no such change occurs
+    /// within the specified timeout.
+    pub fn next_change(
+        &self,
+        interval: Duration,
+        timeout: Option<Duration>,
+    ) -> Result<(SpotifyStatus, SpotifyStatusChange)> {
+        let start = Instant::now();
+        let last = self.status()?;
+        loop {
+            let sleep_time = match timeout {
+                Some(timeout) => match timeout.checked_sub(start.elapsed()) {
+                    Some(remaining) if !remaining.is_zero() => interval.min(remaining),
+                    _ => return Err(SpotifyError::Timeout),
+                },
+                None => interval,
+            };
+            thread::sleep(sleep_time);
+            let curr = self.status()?;
+            let change = SpotifyStatusChange::between(&curr, &last);
+            if change.is_user_visible() {
+                return Ok((curr, change));
+            }
+        }
+    }
+    /// Polls the client status in the specified interval until the
+    /// specified predicate holds, and returns the matching status.
+    /// The first status is checked right away.
+    ///
+    /// Returns `SpotifyError::Timeout` if the predicate
+    /// doesn't hold within the specified timeout.
+    pub fn wait_for<F>(
+        &self,
+        predicate: F,
+        interval: Duration,
+        timeout: Option<Duration>,
+    ) -> Result<SpotifyStatus>
+    where
+        F: Fn(&SpotifyStatus) -> bool,
+    {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let status = self.status()?;
+            if predicate(&status) {
+                return Ok(status);
+            }
+            let sleep_time = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if !remaining.is_zero() => interval.min(remaining),
+                    _ => return Err(SpotifyError::Timeout),
+                },
+                None => interval,
+            };
+            thread::sleep(sleep_time);
+        }
+    }
+    /// Waits until the current track has played past the specified
+    /// fraction of its length, polling in the specified interval.
+    /// Returns the track once that point is reached.
+    ///
+    /// Returns `SpotifyError::TrackChanged` if the track changes first,
+    /// and `SpotifyError::NothingPlaying` if no track is loaded.
+    pub fn wait_for_scrobble_point(
+        &self,
+        fraction: f32,
+        poll_interval: Duration,
+    ) -> Result<SimpleTrack> {
+        let fraction = fraction.clamp(0_f32, 1_f32);
+        let status = self.status()?;
+        if status.try_track().is_none() {
+            return Err(SpotifyError::NothingPlaying);
+        }
+        let uri = status.full_track().track.uri;
+        loop {
+            let status = self.status()?;
+            let track = status.full_track();
+            if track.track.uri != uri {
+                return Err(SpotifyError::TrackChanged);
+            }
+            let length = track.length_secs() as f32;
+            if length > 0_f32 && status.playing_position() >= length * fraction {
+                return Ok(status.track());
+            }
+            thread::sleep(poll_interval);
+        }
+    }
+    /// Seeks to the specified position within the current track.
+    ///
+    /// Returns `SpotifyError::NothingPlaying` if no track is loaded.
+    pub fn seek(&self, position: Duration) -> Result<bool> {
+        let status = self.status()?;
+        if status.try_track().is_none() {
+            return Err(SpotifyError::NothingPlaying);
+        }
+        let uri = status.full_track().track.uri;
+        Ok(self.connector.request_seek(uri, position))
+    }
+    /// Seeks to the specified fraction of the current track,
+    /// where `0.0` is the start and `1.0` is the end of the track.
+    /// The fraction is clamped to that range.
+    ///
+    /// Returns `SpotifyError::NothingPlaying` if no track
+    /// is loaded or the length of the track is unknown.
+    pub fn seek_fraction(&self, fraction: f32) -> Result<bool> {
+        let status = self.status()?;
+        let track = status.full_track();
+        if status.try_track().is_none() || track.length_secs() == 0 {
+            return Err(SpotifyError::NothingPlaying);
+        }
+        let position = track.length_secs() as f32 * fraction.clamp(0_f32, 1_f32);
+        Ok(self
+            .connector
+            .request_seek(track.track.uri, Duration::from_secs_f32(position)))
+    }
+    /// Seeks forward by the specified duration within the current track.
+    ///
+    /// The position is clamped to the end of the track, in which case
+    /// the client moves on as if the track had finished playing.
+    /// Returns `SpotifyError::NothingPlaying` if no track is loaded.
+    pub fn fast_forward(&self, by: Duration) -> Result<bool> {
+        self.seek_by(by.as_secs_f32())
+    }
+    /// Seeks back by the specified duration within the current track.
+    ///
+    /// The position is clamped to the start of the track.
+    /// Returns `SpotifyError::NothingPlaying` if no track is loaded.
+    pub fn rewind(&self, by: Duration) -> Result<bool> {
+        self.seek_by(-by.as_secs_f32())
+    }
+    /// Seeks by the specified offset in seconds relative to
+    /// the estimated position, clamped to the current track.
+    fn seek_by(&self, offset: f32) -> Result<bool> {
+        let status = self.status()?;
+        if status.try_track().is_none() {
+            return Err(SpotifyError::NothingPlaying);
+        }
+        let track = status.full_track();
+        let position = (status.estimated_position() + offset).max(0_f32);
+        let position = match track.length_secs() {
+            0 => position,
+            length => position.min(length as f32),
+        };
+        Ok(self
+            .connector
+            .request_seek(track.track.uri, Duration::from_secs_f32(position)))
+    }
+    /// Plays a track, or any other `spotify:<kind>:<id>` URI
+    /// the client accepts, such as an album, playlist or artist.
+    /// Broken (but recoverable) URIs are fixed first.
+    /// Use `play_track` to play a validated track URI.
+    pub fn play(&self, uri: String) -> bool {
+        self.connector.request_play(normalize_uri(&uri))
+    }
+    /// Plays the specified track.
+    pub fn play_track(&self, track: TrackUri) -> bool {
+        self.try_play(&track).is_ok()
+    }
+    /// Plays an album from the beginning.
+    /// Playback continues through the rest of the album.
+    pub fn play_album(&self, album_uri: String) -> bool {
+        let album = normalize_uri(&album_uri);
+        self.connector.request_play_in_context(album.clone(), album)
+    }
+    /// Plays the tracks of an artist.
+    /// Playback continues through the artist's tracks.
+    pub fn play_artist(&self, artist_uri: String) -> bool {
+        let artist = normalize_uri(&artist_uri);
+        self.connector
+            .request_play_in_context(artist.clone(), artist)
+    }
+    /// Plays the specified track, failing with the error of the request.
+    fn try_play(&self, track: &TrackUri) -> Result<()> {
+        Ok(self.connector.try_request_play(track.to_string())?)
+    }
+    /// Plays a track and waits until the client is playing it,
+    /// polling every 250ms. Returns the track once it started.
+    /// Fails with `SpotifyError::InvalidTrackUri` if the track
+    /// can't be parsed into a `TrackUri`.
+    ///
+    /// Returns `SpotifyError::Timeout` if the track
+    /// didn't start within the specified timeout.
+    pub fn play_and_confirm(&self, track: String, timeout: Duration) -> Result<SimpleTrack> {
+        let uri: TrackUri = track.parse()?;
+        self.try_play(&uri)?;
+        let status = self.wait_for(
+            |status| status.is_playing() && status.track_ref().track.uri == uri.as_str(),
+            Duration::from_millis(250),
+            Some(timeout),
+        )?;
+        Ok(status.track())
+    }
+    /// Plays a list of tracks, one after another.
+    /// Fails with `SpotifyError::InvalidTrackUri` before playing
+    /// anything if a track can't be parsed into a `TrackUri`.
+    ///
+    /// The local API has no way to queue tracks, so only the first
+    /// track is played. If there are more tracks, this fails with
+    /// `SpotifyError::Unsupported` after starting the first one.
+    /// An empty list is a no-op.
+    pub fn play_queue(&self, tracks: Vec<String>) -> Result<()> {
+        let tracks = tracks
+            .iter()
+            .map(|track| track.parse())
+            .collect::<std::result::Result<Vec<TrackUri>, _>>()?;
+        let first = match tracks.first() {
+            Some(track) => track,
+            None => return Ok(()),
         };
-        // Play the track
-        self.connector.request_play(track)
+        self.try_play(first)?;
+        if tracks.len() > 1 {
+            return Err(SpotifyError::Unsupported(Capability::Queue));
+        }
+        Ok(())
+    }
+    /// Plays a list of tracks, one after another, by playing the first
+    /// track and starting the next one whenever the current one ends.
+    /// Fails with `SpotifyError::InvalidTrackUri` before playing
+    /// anything if a track can't be parsed into a `TrackUri`.
+    ///
+    /// The local API has no way to queue tracks, so the sequence is
+    /// advanced by a polling thread sharing this connection, whose handle
+    /// is returned. The sequence only advances while that thread runs.
+    /// A track counts as ended like in `on_track_end`, even if the client
+    /// moved on to another track by itself. The sequence stops after the
+    /// last track, or once another track is played before the current
+    /// one has ended.
+    pub fn play_sequence(&self, uris: Vec<String>) -> Result<PollHandle> {
+        let mut queue = uris
+            .iter()
+            .map(|uri| uri.parse())
+            .collect::<std::result::Result<VecDeque<TrackUri>, _>>()?;
+        let mut current = queue.pop_front();
+        if let Some(ref track) = current {
+            self.try_play(track)?;
+        }
+        // The last status of the current track while it was playing.
+        let mut last: Option<SpotifyStatus> = None;
+        self.share().poll(move |client, status, _| {
+            let is_current = match current {
+                Some(ref track) => status.track_ref().track.uri == track.as_str(),
+                None => return false,
+            };
+            if !last.as_ref().is_some_and(|last| track_ended(last, &status)) {
+                if !is_current {
+                    // Keep waiting until the track started,
+                    // and stop once another track took over.
+                    return last.is_none();
+                }
+                if status.is_playing() {
+                    last = Some(status);
+                }
+                return true;
+            }
+            current = queue.pop_front();
+            last = None;
+            match current {
+                Some(ref next) => client.try_play(next).is_ok(),
+                None => false,
+            }
+        })
     }
     /// Pauses the currently playing track.
     /// Has no effect if the track is already paused.
     pub fn pause(&self) -> bool {
         self.connector.request_pause(true)
     }
+    /// Mutes the client, remembering the current volume for `unmute`.
+    /// Muting an already muted client keeps the remembered volume.
+    ///
+    /// The local API has no end-point to change the volume, so unless
+    /// the volume already is zero, this fails with
+    /// `SpotifyError::Unsupported(Capability::Volume)` and nothing
+    /// is remembered.
+    pub fn mute(&self) -> Result<()> {
+        let volume = self.volume()?;
+        apply_volume(volume, 0_f32)?;
+        self.muted_volume.lock().unwrap().get_or_insert(volume);
+        Ok(())
+    }
+    /// Unmutes the client, restoring the volume remembered by `mute`.
+    /// Has no effect if the client wasn't muted using `mute`.
+    ///
+    /// The remembered volume is restored even if the volume was changed
+    /// while muted. The local API has no end-point to change the volume
+    /// though, so unless the client already plays at the remembered
+    /// volume, this fails with `SpotifyError::Unsupported(Capability::Volume)`
+    /// and the volume stays remembered.
+    pub fn unmute(&self) -> Result<()> {
+        let mut muted_volume = self.muted_volume.lock().unwrap();
+        let remembered = match *muted_volume {
+            Some(volume) => volume,
+            None => return Ok(()),
+        };
+        apply_volume(self.volume()?, remembered)?;
+        *muted_volume = None;
+        Ok(())
+    }
+    /// Toggles shuffle mode, returning whether it is now enabled.
+    ///
+    /// The current mode is read from the status, and an error fetching
+    /// it is returned as is. The local API has no end-point to change the
+    /// shuffle mode though, so applying the flipped mode always fails with
+    /// `SpotifyError::Unsupported(Capability::Shuffle)`.
+    pub fn toggle_shuffle(&self) -> Result<bool> {
+        let current = self.status()?.shuffle_enabled();
+        apply_mode(current, !current, Capability::Shuffle)?;
+        Ok(!current)
+    }
+    /// Toggles repeat mode, returning whether it is now enabled.
+    ///
+    /// The current mode is read from the status, and an error fetching
+    /// it is returned as is. The local API has no end-point to change the
+    /// repeat mode though, so applying the flipped mode always fails with
+    /// `SpotifyError::Unsupported(Capability::Repeat)`.
+    pub fn toggle_repeat(&self) -> Result<bool> {
+        let current = self.status()?.repeat_enabled();
+        apply_mode(current, !current, Capability::Repeat)?;
+        Ok(!current)
+    }
+    /// Enables or disables the private session.
+    ///
+    /// The local API has no end-point to toggle the private session,
+    /// so this always fails with
+    /// `SpotifyError::Unsupported(Capability::PrivateSession)`. The state
+    /// can still be read using `SpotifyStatus::is_private_session`.
+    pub fn set_private_session(&self, _enabled: bool) -> Result<()> {
+        Err(SpotifyError::Unsupported(Capability::PrivateSession))
+    }
+    /// Skips to the next track.
+    ///
+    /// The local API has no end-point to skip tracks,
+    /// so this always fails with `SpotifyError::Unsupported`.
+    pub fn next(&self) -> Result<()> {
+        Err(SpotifyError::Unsupported(Capability::Next))
+    }
+    /// Skips to the previous track.
+    ///
+    /// The local API has no end-point to skip tracks,
+    /// so this always fails with `SpotifyError::Unsupported`.
+    pub fn prev(&self) -> Result<()> {
+        Err(SpotifyError::Unsupported(Capability::Prev))
+    }
     /// Resumes the currently paused track.
     /// Has no effect if the track is already playing.
     pub fn resume(&self) -> bool {
         self.connector.request_pause(false)
     }
+    /// Reaches the specified state, issuing only the commands
+    /// needed based on the current status.
+    ///
+    /// The changes are applied in the order track, playing state.
+    /// A different track is played first, which also resumes playback,
+    /// so that `playing: Some(false)` pauses the new track afterwards.
+    ///
+    /// The local API can change neither the volume nor the shuffle mode.
+    /// If either would have to change, this fails with
+    /// `SpotifyError::Unsupported` before issuing any command.
+    /// So does an invalid track, with `SpotifyError::InvalidTrackUri`.
+    pub fn set_state(&self, desired: DesiredState) -> Result<()> {
+        let track = match desired.track {
+            Some(ref track) => Some(track.parse::<TrackUri>()?),
+            None => None,
+        };
+        let status = self.status()?;
+        if let Some(volume) = desired.volume {
+            apply_volume(status.volume(), volume)?;
+        }
+        if let Some(shuffle) = desired.shuffle {
+            apply_mode(status.shuffle_enabled(), shuffle, Capability::Shuffle)?;
+        }
+        let mut playing = status.is_playing();
+        if let Some(track) = track {
+            if status.track_ref().track.uri != track.as_str() {
+                self.try_play(&track)?;
+                playing = true;
+            }
+        }
+        match desired.playing {
+            Some(desired) if desired != playing => {
+                Ok(self.connector.try_request_pause(!desired)?)
+            }
+            _ => Ok(()),
+        }
+    }
+    /// Resumes the current track only if it is paused,
+    /// instead of toggling the playing state.
+    /// Returns whether the track was resumed.
+    pub fn play_if_paused(&self) -> Result<bool> {
+        match self.status()?.playback_state() {
+            PlaybackState::Paused(_) => Ok(self.resume()),
+            _ => Ok(false),
+        }
+    }
+    /// Pauses the current track only if it is playing,
+    /// instead of toggling the playing state.
+    /// Returns whether the track was paused.
+    pub fn pause_if_playing(&self) -> Result<bool> {
+        match self.status()?.playback_state() {
+            PlaybackState::Playing(_) => Ok(self.pause()),
+            _ => Ok(false),
+        }
+    }
+    /// Gets read-only access to the OAuth token used to authenticate
+    /// requests, empty if none was fetched.
+    ///
+    /// The token is sensitive: anyone holding it can control
+    /// the Spotify client, so don't log or share it.
+    /// The returned token is a shared snapshot, which derefs to `str`.
+    /// It isn't updated when the keepalive refreshes the tokens,
+    /// so get the token again instead of holding on to it.
+    pub fn oauth_token(&self) -> Arc<str> {
+        self.connector.oauth_token()
+    }
+    /// Gets read-only access to the CSRF token used to authenticate
+    /// requests.
+    ///
+    /// The token is sensitive: anyone holding it can control
+    /// the Spotify client, so don't log or share it.
+    /// The returned token is a shared snapshot, which derefs to `str`.
+    /// It isn't updated when the keepalive refreshes the tokens,
+    /// so get the token again instead of holding on to it.
+    pub fn csrf_token(&self) -> Arc<str> {
+        self.connector.csrf_token()
+    }
+    /// Gets the port of the local Spotify server.
+    pub fn port(&self) -> u16 {
+        self.connector.port()
+    }
+    /// Queries an arbitrary end-point of the local Spotify server,
+    /// such as `remote/status.json`, and returns the parsed response.
+    ///
+    /// This is an escape hatch for end-points the typed API doesn't
+    /// cover yet. The parameter values are percent-encoded, and the
+    /// OAuth and/or CSRF tokens are included on request.
+    pub fn query_raw(
+        &self,
+        endpoint: &str,
+        params: &[(&str, &str)],
+        with_oauth: bool,
+        with_csrf: bool,
+    ) -> Result<JsonValue> {
+        Ok(self
+            .connector
+            .query_raw(endpoint, params, with_oauth, with_csrf)?)
+    }
+    /// Tests whether the client supports the specified capability.
+    ///
+    /// The local API only exposes the `status`, `play`, `pause` and `open`
+    /// end-points. Seeking is possible by playing the current track with
+    /// a position fragment appended to its URI, but there is no way to
+    /// change the volume, shuffle or repeat mode, the private session,
+    /// or to skip or queue tracks.
+    pub fn supports(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Seek => true,
+            Capability::Volume
+            | Capability::Shuffle
+            | Capability::Repeat
+            | Capability::Next
+            | Capability::Prev
+            | Capability::PrivateSession
+            | Capability::Queue => false,
+        }
+    }
+    /// Shuts down the Spotify client.
+    /// Returns whether the client was asked to shut down.
+    ///
+    /// The local API has no shutdown end-point, so the client process
+    /// is terminated instead: on Windows using `TerminateProcess`
+    /// (requires the `windows-process` feature), and on Linux and macOS
+    /// by sending `SIGTERM` via `pkill`.
+    pub fn quit(&self) -> bool {
+        Spotify::terminate_client()
+    }
     /// Tests whether the SpotifyWebHelper process is running.
-    #[cfg(windows)]
+    ///
+    /// If the processes cannot be enumerated, the process is assumed
+    /// to be running, so that connecting is attempted anyway.
+    #[cfg(all(windows, feature = "windows-process"))]
     fn spotify_webhelper_alive() -> bool {
         let process = "SpotifyWebHelper.exe";
-        WindowsProcess::find_by_name(process).is_some()
+        match WindowsProcess::find_by_name(process) {
+            Ok(process) => process.is_some(),
+            Err(error) => {
+                warn!(
+                    "Unable to enumerate processes, connecting anyway: {}",
+                    error
+                );
+                true
+            }
+        }
+    }
+    /// Terminates all Spotify client processes.
+    #[cfg(all(windows, feature = "windows-process"))]
+    fn terminate_client() -> bool {
+        match WindowsProcess::find_all_by_name("Spotify.exe") {
+            Ok(processes) => {
+                !processes.is_empty() && processes.iter().all(WindowsProcess::terminate)
+            }
+            Err(_) => false,
+        }
+    }
+    /// Terminates all Spotify client processes.
+    #[cfg(unix)]
+    fn terminate_client() -> bool {
+        let process = if cfg!(target_os = "macos") {
+            "Spotify"
+        } else {
+            "spotify"
+        };
+        Command::new("pkill")
+            .args(["-TERM", "-x", process])
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false)
+    }
+    /// Terminates all Spotify client processes.
+    #[cfg(not(any(unix, all(windows, feature = "windows-process"))))]
+    fn terminate_client() -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Capability, ConnectionState, PollOptions, Spotify, SpotifyError};
+    use crate::test_server::{config_for, spotify_routes, TestServer, STATUS};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    const TRACK_A: &str = "spotify:track:4uLU6hMCjMI75M1A2tKUQC";
+    const TRACK_B: &str = "spotify:track:1pGZIV8olkbRMjyHWoEXyt";
+    const TRACK_AUTOPLAY: &str = "spotify:track:7GhIk7Il098yCjg4BQjzvb";
+
+    fn connect(server: &TestServer) -> Spotify {
+        Spotify::new_unchecked(&config_for(server)).unwrap()
+    }
+
+    /// Builds a status playing the specified track.
+    fn status(uri: &str, length: i32, position: f32) -> String {
+        let mut json = json::parse(STATUS).unwrap();
+        json["track"]["track_resource"]["uri"] = uri.into();
+        json["track"]["length"] = length.into();
+        json["playing_position"] = position.into();
+        json.dump()
+    }
+
+    /// Builds a status with the specified volume.
+    fn status_with_volume(volume: f32) -> String {
+        let mut json = json::parse(STATUS).unwrap();
+        json["volume"] = volume.into();
+        json.dump()
+    }
+
+    /// Builds a status with the specified changes applied.
+    fn status_where<F: FnOnce(&mut json::JsonValue)>(change: F) -> String {
+        let mut json = json::parse(STATUS).unwrap();
+        change(&mut json);
+        json.dump()
+    }
+
+    /// Gets the requests received for the specified path.
+    fn requests_to(server: &TestServer, path: &str) -> Vec<String> {
+        server
+            .requests()
+            .into_iter()
+            .filter(|target| target.starts_with(path))
+            .collect()
+    }
+
+    /// Gets the percent-encoded `uri` parameter for the specified uri.
+    fn uri_param(uri: &str) -> String {
+        format!("uri={}", uri.replace(':', "%3A").replace('#', "%23"))
+    }
+
+    /// Waits until the predicate holds, panicking after a few seconds.
+    fn wait_until<P: Fn() -> bool>(predicate: P) {
+        let start = Instant::now();
+        while !predicate() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn play_sequence_advances_past_autoplayed_tracks() {
+        let server = TestServer::start(spotify_routes());
+        server.set_route("/remote/status.json", status(TRACK_A, 213, 212.0));
+        let spotify = connect(&server);
+        let handle = spotify
+            .play_sequence(vec![TRACK_A.to_owned(), TRACK_B.to_owned()])
+            .unwrap();
+        // The sequence is driven off the existing connection.
+        let token_fetches = server
+            .requests()
+            .iter()
+            .filter(|target| target.starts_with("/simplecsrf/token.json"))
+            .count();
+        assert_eq!(token_fetches, 1);
+        wait_until(|| handle.stats().successful_fetches > 0);
+        // The client autoplays a longer track after the first one.
+        server.set_route("/remote/status.json", status(TRACK_AUTOPLAY, 400, 1.0));
+        let played = |uri: &str| {
+            let param = format!("uri={}", uri.replace(':', "%3A"));
+            server
+                .requests()
+                .iter()
+                .any(|target| target.starts_with("/remote/play.json") && target.contains(&param))
+        };
+        wait_until(|| played(TRACK_B));
+        server.set_route("/remote/status.json", status(TRACK_B, 213, 212.0));
+        let fetches = handle.stats().successful_fetches;
+        wait_until(|| handle.stats().successful_fetches > fetches);
+        server.set_route("/remote/status.json", status(TRACK_AUTOPLAY, 400, 1.0));
+        // The sequence ends after the last track.
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn reconnect_moves_through_the_connection_states() {
+        let server = TestServer::start(spotify_routes());
+        let mut spotify = connect(&server);
+        let state = spotify.state.clone();
+        let name = move || match *state.lock().unwrap() {
+            ConnectionState::Disconnected => "Disconnected",
+            ConnectionState::Connecting => "Connecting",
+            ConnectionState::Connected => "Connected",
+            ConnectionState::Reconnecting => "Reconnecting",
+            ConnectionState::Failed(_) => "Failed",
+        };
+        let mut seen = vec![name()];
+        spotify.reconnect();
+        seen.push(name());
+        spotify.reconnect();
+        seen.push(name());
+        // Hold back the tokens to observe the handshake in progress.
+        let guard = server.hold();
+        let reconnect = thread::spawn(move || {
+            spotify.reconnect();
+            spotify
+        });
+        wait_until(|| name() == "Connecting");
+        seen.push(name());
+        drop(guard);
+        let mut spotify = reconnect.join().unwrap();
+        seen.push(name());
+        server.set_route("/simplecsrf/token.json", "null".to_owned());
+        for _ in 0..3 {
+            spotify.reconnect();
+            seen.push(name());
+        }
+        assert_eq!(
+            seen,
+            [
+                "Connected",
+                "Disconnected",
+                "Reconnecting",
+                "Connecting",
+                "Connected",
+                "Disconnected",
+                "Reconnecting",
+                "Failed",
+            ]
+        );
+    }
+
+    #[test]
+    fn poll_marks_the_connection_as_established_after_a_fetch() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        let state = spotify.state.clone();
+        server.set_route("/remote/status.json", "null".to_owned());
+        server.set_route("/simplecsrf/token.json", "null".to_owned());
+        let handle = spotify
+            .poll_with(
+                PollOptions::new().interval(Duration::from_millis(10)),
+                |_, _, _| false,
+            )
+            .unwrap();
+        wait_until(|| matches!(handle.state(), ConnectionState::Failed(_)));
+        // The old tokens still work, while new ones can't be fetched.
+        server.set_route("/remote/status.json", STATUS.to_owned());
+        handle.join().unwrap();
+        assert!(matches!(*state.lock().unwrap(), ConnectionState::Connected));
+    }
+
+    #[test]
+    fn stop_ends_polling() {
+        let server = TestServer::start(spotify_routes());
+        let handle = connect(&server)
+            .poll_with(
+                PollOptions::new().interval(Duration::from_millis(10)),
+                |_, _, _| true,
+            )
+            .unwrap();
+        wait_until(|| handle.stats().successful_fetches > 0);
+        handle.stop();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn supports_only_seeking_beyond_play_and_pause() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        assert!(spotify.supports(Capability::Seek));
+        assert!(!spotify.supports(Capability::Volume));
+        assert!(!spotify.supports(Capability::Shuffle));
+        assert!(!spotify.supports(Capability::PrivateSession));
+    }
+
+    #[test]
+    fn mute_remembers_the_volume_for_unmute() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        // The local API can't change the volume of 0.75.
+        assert!(matches!(
+            spotify.mute(),
+            Err(SpotifyError::Unsupported(Capability::Volume))
+        ));
+        assert_eq!(*spotify.muted_volume.lock().unwrap(), None);
+        server.set_route("/remote/status.json", status_with_volume(0.0));
+        spotify.mute().unwrap();
+        assert_eq!(*spotify.muted_volume.lock().unwrap(), Some(0.0));
+        // The remembered volume is restored, even if it was changed.
+        server.set_route("/remote/status.json", status_with_volume(0.5));
+        assert!(matches!(
+            spotify.unmute(),
+            Err(SpotifyError::Unsupported(Capability::Volume))
+        ));
+        server.set_route("/remote/status.json", status_with_volume(0.0));
+        spotify.unmute().unwrap();
+        assert_eq!(*spotify.muted_volume.lock().unwrap(), None);
+    }
+
+    #[test]
+    fn set_private_session_is_unsupported() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        assert!(matches!(
+            spotify.set_private_session(true),
+            Err(SpotifyError::Unsupported(Capability::PrivateSession))
+        ));
+        // Nothing is sent to the client.
+        assert!(!server
+            .requests()
+            .iter()
+            .any(|target| target.starts_with("/remote/") && !target.starts_with("/remote/open")));
+    }
+
+    #[test]
+    fn toggles_read_the_mode_before_failing() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        assert!(matches!(
+            spotify.toggle_shuffle(),
+            Err(SpotifyError::Unsupported(Capability::Shuffle))
+        ));
+        assert!(matches!(
+            spotify.toggle_repeat(),
+            Err(SpotifyError::Unsupported(Capability::Repeat))
+        ));
+        // Errors reading the status are returned instead of guessing.
+        server.set_route("/remote/status.json", "null".to_owned());
+        assert!(matches!(
+            spotify.toggle_shuffle(),
+            Err(SpotifyError::InternalError(_))
+        ));
+        assert!(matches!(
+            spotify.toggle_repeat(),
+            Err(SpotifyError::InternalError(_))
+        ));
+    }
+
+    #[test]
+    fn next_and_prev_are_unsupported() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        assert!(!spotify.supports(Capability::Next));
+        assert!(matches!(
+            spotify.next(),
+            Err(SpotifyError::Unsupported(Capability::Next))
+        ));
+        assert!(matches!(
+            spotify.prev(),
+            Err(SpotifyError::Unsupported(Capability::Prev))
+        ));
+    }
+
+    #[test]
+    fn play_accepts_any_kind_of_uri() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        assert!(spotify.play("open.spotify.com/album/6N9PS4QXF1D0OWPk0Sxtb4".to_owned()));
+        assert!(spotify.play_track(TRACK_A.parse().unwrap()));
+        let played: Vec<_> = server
+            .requests()
+            .into_iter()
+            .filter(|target| target.starts_with("/remote/play.json"))
+            .collect();
+        assert!(played[0].contains("uri=spotify%3Aalbum%3A6N9PS4QXF1D0OWPk0Sxtb4"));
+        assert!(played[1].contains(&format!("uri={}", TRACK_A.replace(':', "%3A"))));
+    }
+
+    #[test]
+    fn tokens_are_exposed_read_only() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        assert_eq!(&*spotify.oauth_token(), "oauth-token");
+        assert_eq!(&*spotify.csrf_token(), "csrf-token");
+    }
+
+    #[test]
+    fn poll_supervised_resumes_after_a_panic() {
+        let server = TestServer::start(spotify_routes());
+        let mut calls = 0;
+        let handle = connect(&server)
+            .poll_supervised_with(
+                PollOptions::new().interval(Duration::from_millis(10)),
+                1,
+                move |_, _, change| {
+                    calls += 1;
+                    if calls == 1 {
+                        panic!("buggy callback");
+                    }
+                    // The restart starts over with a fresh status.
+                    assert_eq!(calls, 2);
+                    assert!(change.track);
+                    false
+                },
+            )
+            .unwrap();
+        // The statistics accumulate across the restart.
+        wait_until(|| handle.stats().successful_fetches >= 2);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn seek_sends_the_position_with_the_track() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        assert!(spotify.seek(Duration::from_secs(90)).unwrap());
+        let plays = requests_to(&server, "/remote/play.json");
+        assert_eq!(plays.len(), 1);
+        assert!(plays[0].contains(&uri_param(&format!("{}#1:30", TRACK_A))));
+    }
+
+    #[test]
+    fn seek_fraction_is_clamped_to_the_track() {
+        let server = TestServer::start(spotify_routes());
+        server.set_route("/remote/status.json", status(TRACK_A, 200, 0.0));
+        let spotify = connect(&server);
+        assert!(spotify.seek_fraction(0.5).unwrap());
+        assert!(spotify.seek_fraction(2.0).unwrap());
+        let plays = requests_to(&server, "/remote/play.json");
+        assert_eq!(plays.len(), 2);
+        assert!(plays[0].contains(&uri_param(&format!("{}#1:40", TRACK_A))));
+        assert!(plays[1].contains(&uri_param(&format!("{}#3:20", TRACK_A))));
+    }
+
+    #[test]
+    fn seeking_needs_a_track() {
+        let server = TestServer::start(spotify_routes());
+        server.set_route(
+            "/remote/status.json",
+            status_where(|json| {
+                json.remove("track");
+            }),
+        );
+        let spotify = connect(&server);
+        let nothing_playing =
+            |result: super::Result<bool>| matches!(result, Err(SpotifyError::NothingPlaying));
+        assert!(nothing_playing(spotify.seek(Duration::from_secs(1))));
+        assert!(nothing_playing(spotify.seek_fraction(0.5)));
+        assert!(nothing_playing(
+            spotify.fast_forward(Duration::from_secs(1))
+        ));
+        assert!(nothing_playing(spotify.rewind(Duration::from_secs(1))));
+        // Without a length, there is no fraction to seek to.
+        server.set_route("/remote/status.json", status(TRACK_A, 0, 0.0));
+        assert!(nothing_playing(spotify.seek_fraction(0.5)));
+        assert!(requests_to(&server, "/remote/play.json").is_empty());
+    }
+
+    #[test]
+    fn seeking_reports_failed_requests() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        server.hang_up("/remote/play.json");
+        assert!(!spotify.seek(Duration::from_secs(1)).unwrap());
+        assert!(!spotify.fast_forward(Duration::from_secs(1)).unwrap());
+        server.hang_up("/remote/status.json");
+        assert!(matches!(
+            spotify.seek(Duration::from_secs(1)),
+            Err(SpotifyError::InternalError(_))
+        ));
+        assert!(matches!(
+            spotify.rewind(Duration::from_secs(1)),
+            Err(SpotifyError::InternalError(_))
+        ));
+    }
+
+    #[test]
+    fn fast_forward_and_rewind_are_clamped_to_the_track() {
+        let server = TestServer::start(spotify_routes());
+        // A paused track stays at the reported position.
+        server.set_route(
+            "/remote/status.json",
+            status_where(|json| json["playing"] = false.into()),
+        );
+        let spotify = connect(&server);
+        assert!(spotify.fast_forward(Duration::from_secs(10)).unwrap());
+        assert!(spotify.fast_forward(Duration::from_secs(500)).unwrap());
+        assert!(spotify.rewind(Duration::from_secs(20)).unwrap());
+        assert!(spotify.rewind(Duration::from_secs(60)).unwrap());
+        let plays = requests_to(&server, "/remote/play.json");
+        let positions = ["0:52", "3:33", "0:22", "0:00"];
+        assert_eq!(plays.len(), positions.len());
+        for (play, position) in plays.iter().zip(positions.iter()) {
+            assert!(play.contains(&uri_param(&format!("{}#{}", TRACK_A, position))));
+        }
+    }
+
+    #[test]
+    fn wait_for_returns_the_matching_status() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        let fetches = requests_to(&server, "/remote/status.json").len();
+        let status = spotify
+            .wait_for(
+                |status| status.volume() > 0.5,
+                Duration::from_millis(10),
+                Some(Duration::from_secs(5)),
+            )
+            .unwrap();
+        assert_eq!(status.volume(), 0.75);
+        // The first status is checked right away.
+        assert_eq!(
+            requests_to(&server, "/remote/status.json").len(),
+            fetches + 1
+        );
+        thread::scope(|scope| {
+            let waiting = scope.spawn(|| {
+                spotify.wait_for(
+                    |status| status.volume() < 0.5,
+                    Duration::from_millis(10),
+                    Some(Duration::from_secs(5)),
+                )
+            });
+            wait_until(|| requests_to(&server, "/remote/status.json").len() > fetches + 2);
+            server.set_route("/remote/status.json", status_with_volume(0.25));
+            assert_eq!(waiting.join().unwrap().unwrap().volume(), 0.25);
+        });
+    }
+
+    #[test]
+    fn wait_for_times_out() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        let fetches = requests_to(&server, "/remote/status.json").len();
+        let start = Instant::now();
+        let result = spotify.wait_for(
+            |_| false,
+            Duration::from_millis(20),
+            Some(Duration::from_millis(100)),
+        );
+        assert!(matches!(result, Err(SpotifyError::Timeout)));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(requests_to(&server, "/remote/status.json").len() > fetches + 1);
+        server.hang_up("/remote/status.json");
+        let result = spotify.wait_for(|_| true, Duration::from_millis(20), None);
+        assert!(matches!(result, Err(SpotifyError::InternalError(_))));
+    }
+
+    #[test]
+    fn set_state_issues_only_the_needed_commands() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        // The track is already playing.
+        let desired = super::DesiredState {
+            playing: Some(true),
+            volume: Some(0.75),
+            shuffle: Some(false),
+            track: Some(TRACK_A.to_owned()),
+        };
+        spotify.set_state(desired).unwrap();
+        assert!(requests_to(&server, "/remote/play.json").is_empty());
+        assert!(requests_to(&server, "/remote/pause.json").is_empty());
+        // A different track is played, then paused.
+        let desired = super::DesiredState {
+            playing: Some(false),
+            track: Some(TRACK_B.to_owned()),
+            ..super::DesiredState::default()
+        };
+        spotify.set_state(desired).unwrap();
+        let plays = requests_to(&server, "/remote/play.json");
+        assert_eq!(plays.len(), 1);
+        assert!(plays[0].contains(&uri_param(TRACK_B)));
+        let pauses = requests_to(&server, "/remote/pause.json");
+        assert!(pauses[0].contains("pause=true"));
+    }
+
+    #[test]
+    fn set_state_fails_before_issuing_commands() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        let fetches = requests_to(&server, "/remote/status.json").len();
+        let desired = super::DesiredState {
+            track: Some("spotify:album:6N9PS4QXF1D0OWPk0Sxtb4".to_owned()),
+            ..super::DesiredState::default()
+        };
+        assert!(matches!(
+            spotify.set_state(desired),
+            Err(SpotifyError::InvalidTrackUri(_))
+        ));
+        // The track is checked before the status is fetched.
+        assert_eq!(requests_to(&server, "/remote/status.json").len(), fetches);
+        let desired = super::DesiredState {
+            volume: Some(0.5),
+            track: Some(TRACK_B.to_owned()),
+            ..super::DesiredState::default()
+        };
+        assert!(matches!(
+            spotify.set_state(desired),
+            Err(SpotifyError::Unsupported(Capability::Volume))
+        ));
+        let desired = super::DesiredState {
+            playing: Some(false),
+            shuffle: Some(true),
+            ..super::DesiredState::default()
+        };
+        assert!(matches!(
+            spotify.set_state(desired),
+            Err(SpotifyError::Unsupported(Capability::Shuffle))
+        ));
+        assert!(requests_to(&server, "/remote/play.json").is_empty());
+        assert!(requests_to(&server, "/remote/pause.json").is_empty());
+    }
+
+    #[test]
+    fn set_state_reports_failed_requests() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        let desired = super::DesiredState {
+            track: Some(TRACK_B.to_owned()),
+            ..super::DesiredState::default()
+        };
+        server.hang_up("/remote/play.json");
+        assert!(matches!(
+            spotify.set_state(desired.clone()),
+            Err(SpotifyError::InternalError(_))
+        ));
+        server.hang_up("/remote/status.json");
+        assert!(matches!(
+            spotify.set_state(desired),
+            Err(SpotifyError::InternalError(_))
+        ));
+        assert_eq!(requests_to(&server, "/remote/play.json").len(), 1);
+    }
+
+    #[test]
+    fn status_with_retry_gives_up_after_the_last_attempt() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        let fetches = requests_to(&server, "/remote/status.json").len();
+        server.hang_up("/remote/status.json");
+        let start = Instant::now();
+        let result = spotify.status_with_retry(3, Duration::from_millis(20));
+        assert!(matches!(result, Err(SpotifyError::InternalError(_))));
+        // The delay doubles with each retry.
+        assert!(start.elapsed() >= Duration::from_millis(60));
+        assert_eq!(
+            requests_to(&server, "/remote/status.json").len(),
+            fetches + 3
+        );
+        // Malformed statuses aren't retried.
+        server.set_route("/remote/status.json", "[]".to_owned());
+        let result = spotify.status_with_retry(3, Duration::from_millis(20));
+        assert!(matches!(result, Err(SpotifyError::InternalError(_))));
+        assert_eq!(
+            requests_to(&server, "/remote/status.json").len(),
+            fetches + 4
+        );
+    }
+
+    #[test]
+    fn status_with_retry_recovers() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        let fetches = requests_to(&server, "/remote/status.json").len();
+        server.hang_up("/remote/status.json");
+        thread::scope(|scope| {
+            let retrying = scope.spawn(|| spotify.status_with_retry(10, Duration::from_millis(20)));
+            wait_until(|| requests_to(&server, "/remote/status.json").len() > fetches);
+            server.set_route("/remote/status.json", status_with_volume(0.25));
+            assert_eq!(retrying.join().unwrap().unwrap().volume(), 0.25);
+        });
+    }
+
+    #[test]
+    fn play_queue_plays_the_first_track() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        spotify.play_queue(vec![]).unwrap();
+        assert!(requests_to(&server, "/remote/play.json").is_empty());
+        spotify.play_queue(vec![TRACK_A.to_owned()]).unwrap();
+        let result = spotify.play_queue(vec![TRACK_B.to_owned(), TRACK_A.to_owned()]);
+        assert!(matches!(
+            result,
+            Err(SpotifyError::Unsupported(Capability::Queue))
+        ));
+        let plays = requests_to(&server, "/remote/play.json");
+        assert_eq!(plays.len(), 2);
+        assert!(plays[0].contains(&uri_param(TRACK_A)));
+        assert!(plays[1].contains(&uri_param(TRACK_B)));
+    }
+
+    #[test]
+    fn play_queue_fails_before_playing_anything() {
+        let server = TestServer::start(spotify_routes());
+        let spotify = connect(&server);
+        let result = spotify.play_queue(vec![TRACK_A.to_owned(), "not a track".to_owned()]);
+        assert!(matches!(result, Err(SpotifyError::InvalidTrackUri(_))));
+        assert!(requests_to(&server, "/remote/play.json").is_empty());
+        server.hang_up("/remote/play.json");
+        let result = spotify.play_queue(vec![TRACK_A.to_owned(), TRACK_B.to_owned()]);
+        assert!(matches!(result, Err(SpotifyError::InternalError(_))));
     }
 }
@@ -0,0 +1,334 @@
+//! The MPRIS module.
+//!
+//! This module mirrors the polled Spotify status into an MPRIS
+//! `org.mpris.MediaPlayer2.Player` interface on the D-Bus session bus,
+//! so media keys and desktop now-playing widgets can see and control it.
+
+use crate::connector::InternalSpotifyError;
+use crate::status::SpotifyStatus;
+use crate::{Capability, PollHandle, PollOptions, Result, Spotify, SpotifyError};
+use std::collections::HashMap;
+use zbus::blocking::connection::Builder;
+use zbus::fdo;
+use zbus::interface;
+use zbus::zvariant::{ObjectPath, OwnedValue, Value};
+
+// D-Bus names
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.spotifyrs";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const TRACK_PATH_PREFIX: &str = "/org/mpris/MediaPlayer2/Track/";
+
+/// The `org.mpris.MediaPlayer2` root interface.
+struct MprisRoot;
+
+/// The `org.mpris.MediaPlayer2.Player` interface.
+struct MprisPlayer {
+    /// The Spotify API used to serve control calls.
+    spotify: Spotify,
+    /// The most recently polled status.
+    status: Option<SpotifyStatus>,
+}
+
+/// Implements the `org.mpris.MediaPlayer2` interface for `MprisRoot`.
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl MprisRoot {
+    fn raise(&self) {}
+    fn quit(&self) {}
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        "Spotify".to_owned()
+    }
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        vec!["spotify".to_owned()]
+    }
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// Implements the `org.mpris.MediaPlayer2.Player` interface for `MprisPlayer`.
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl MprisPlayer {
+    fn play_pause(&self) {
+        match self.status {
+            Some(ref status) if status.is_playing() => self.spotify.pause(),
+            _ => self.spotify.resume(),
+        };
+    }
+    fn play(&self) {
+        self.spotify.resume();
+    }
+    fn pause(&self) {
+        self.spotify.pause();
+    }
+    fn stop(&self) {
+        self.spotify.pause();
+    }
+    fn next(&self) -> fdo::Result<()> {
+        self.spotify.next().map_err(control_error)
+    }
+    fn previous(&self) -> fdo::Result<()> {
+        self.spotify.prev().map_err(control_error)
+    }
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        playback_status(self.status.as_ref()).to_owned()
+    }
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        match self.status {
+            Some(ref status) => metadata(status),
+            None => HashMap::new(),
+        }
+    }
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        self.status
+            .as_ref()
+            .map(|status| f64::from(status.volume()))
+            .unwrap_or(0_f64)
+    }
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1_f64
+    }
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1_f64
+    }
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1_f64
+    }
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        self.spotify.supports(Capability::Next)
+    }
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        self.spotify.supports(Capability::Prev)
+    }
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        false
+    }
+    #[zbus(property)]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+/// Implements `From<zbus::Error>` for `SpotifyError`.
+impl From<zbus::Error> for SpotifyError {
+    fn from(error: zbus::Error) -> SpotifyError {
+        SpotifyError::InternalError(InternalSpotifyError::DBusError(error))
+    }
+}
+
+/// Converts the error of a control call into a D-Bus error.
+/// Unsupported capabilities are reported as not supported.
+fn control_error(error: SpotifyError) -> fdo::Error {
+    match error {
+        SpotifyError::Unsupported(_) => fdo::Error::NotSupported(error.to_string()),
+        error => fdo::Error::Failed(error.to_string()),
+    }
+}
+
+/// Gets the MPRIS playback status of the specified status.
+fn playback_status(status: Option<&SpotifyStatus>) -> &'static str {
+    match status {
+        Some(status) if status.is_playing() => "Playing",
+        Some(_) => "Paused",
+        None => "Stopped",
+    }
+}
+
+/// Gets the MPRIS metadata of the track of the specified status.
+fn metadata(status: &SpotifyStatus) -> HashMap<String, OwnedValue> {
+    let mut metadata = HashMap::new();
+    let track = status.full_track();
+    let track_id = format!(
+        "{}{}",
+        TRACK_PATH_PREFIX,
+        track
+            .track
+            .uri
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+    );
+    let mut insert = |key: &str, value: Value| {
+        if let Ok(value) = value.try_to_owned() {
+            metadata.insert(key.to_owned(), value);
+        }
+    };
+    if let Ok(path) = ObjectPath::try_from(track_id) {
+        insert("mpris:trackid", Value::from(path));
+    }
+    insert(
+        "mpris:length",
+        Value::from(i64::from(track.length_secs()) * 1_000_000),
+    );
+    let artists: Vec<String> = track
+        .artists()
+        .into_iter()
+        .map(|artist| artist.name)
+        .collect();
+    insert("xesam:artist", Value::from(artists));
+    insert("xesam:title", Value::from(track.track.name));
+    insert("xesam:album", Value::from(track.album.name));
+    insert("xesam:url", Value::from(track.track.location.og));
+    metadata
+}
+
+/// Implements `Spotify`.
+impl Spotify {
+    /// Serves the client status as an MPRIS media player on the
+    /// D-Bus session bus, like `serve_mpris_with` using the
+    /// default `PollOptions`.
+    ///
+    /// Only available on Linux with the `mpris` feature enabled.
+    pub fn serve_mpris(self) -> Result<PollHandle> {
+        self.serve_mpris_with(PollOptions::new())
+    }
+    /// Serves the client status as an MPRIS media player on the
+    /// D-Bus session bus. Returns the `PollHandle` of the polling
+    /// thread, which keeps the bus name until it is stopped.
+    ///
+    /// The status is polled using the specified options and mirrored
+    /// into the `org.mpris.MediaPlayer2.Player` interface. The
+    /// `PlayPause`, `Play`, `Pause` and `Stop` calls are mapped onto
+    /// `pause` and `resume`, and `Next` and `Previous` onto `next` and
+    /// `prev`. `CanGoNext` and `CanGoPrevious` follow `supports`, so
+    /// clients can disable the controls the local API lacks.
+    ///
+    /// Only available on Linux with the `mpris` feature enabled.
+    pub fn serve_mpris_with(self, options: PollOptions) -> Result<PollHandle> {
+        let poller = Spotify::new_unchecked(&self.config)?;
+        let player = MprisPlayer {
+            spotify: self,
+            status: None,
+        };
+        let connection = Builder::session()?
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, MprisRoot)?
+            .serve_at(OBJECT_PATH, player)?
+            .build()?;
+        let player = connection
+            .object_server()
+            .interface::<_, MprisPlayer>(OBJECT_PATH)?;
+        poller.poll_with(options, move |_, curr, change| {
+            // Keep the connection alive for as long as we serve.
+            let _ = &connection;
+            player.get_mut().status = Some(curr);
+            let iface = player.get();
+            let ctxt = player.signal_context();
+            if change.playing {
+                let _ = zbus::block_on(iface.playback_status_changed(ctxt));
+            }
+            if change.track {
+                let _ = zbus::block_on(iface.metadata_changed(ctxt));
+            }
+            if change.volume {
+                let _ = zbus::block_on(iface.volume_changed(ctxt));
+            }
+            true
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{metadata, playback_status, MprisPlayer};
+    use crate::status::SpotifyStatus;
+    use crate::test_server::{config_for, spotify_routes, TestServer, STATUS};
+    use crate::{Capability, Spotify};
+    use zbus::fdo;
+    use zbus::zvariant::{ObjectPath, Value};
+
+    fn status(edit: impl FnOnce(&mut json::JsonValue)) -> SpotifyStatus {
+        let mut json = json::parse(STATUS).unwrap();
+        edit(&mut json);
+        SpotifyStatus::from(json)
+    }
+
+    #[test]
+    fn playback_status_follows_the_playing_state() {
+        let paused = status(|json| json["playing"] = false.into());
+        assert_eq!(playback_status(Some(&status(|_| {}))), "Playing");
+        assert_eq!(playback_status(Some(&paused)), "Paused");
+        assert_eq!(playback_status(None), "Stopped");
+    }
+
+    #[test]
+    fn metadata_describes_the_track() {
+        let metadata = metadata(&status(|_| {}));
+        let value = |key: &str| Value::from(metadata[key].try_clone().unwrap());
+        assert_eq!(
+            value("mpris:trackid"),
+            Value::from(
+                ObjectPath::try_from(
+                    "/org/mpris/MediaPlayer2/Track/spotify_track_4uLU6hMCjMI75M1A2tKUQC"
+                )
+                .unwrap()
+            )
+        );
+        assert_eq!(value("mpris:length"), Value::from(213_000_000_i64));
+        assert_eq!(value("xesam:title"), Value::from("Never Gonna Give You Up"));
+        assert_eq!(
+            value("xesam:artist"),
+            Value::from(vec!["Rick Astley".to_owned()])
+        );
+    }
+
+    #[test]
+    fn metadata_reports_negative_lengths_as_zero() {
+        let metadata = metadata(&status(|json| json["track"]["length"] = (-1).into()));
+        assert_eq!(
+            Value::from(metadata["mpris:length"].try_clone().unwrap()),
+            Value::from(0_i64)
+        );
+    }
+
+    #[test]
+    fn skipping_follows_the_supported_capabilities() {
+        let server = TestServer::start(spotify_routes());
+        let player = MprisPlayer {
+            spotify: Spotify::new_unchecked(&config_for(&server)).unwrap(),
+            status: None,
+        };
+        assert_eq!(
+            player.can_go_next(),
+            player.spotify.supports(Capability::Next)
+        );
+        assert_eq!(
+            player.can_go_previous(),
+            player.spotify.supports(Capability::Prev)
+        );
+        assert!(matches!(player.next(), Err(fdo::Error::NotSupported(_))));
+        assert!(matches!(
+            player.previous(),
+            Err(fdo::Error::NotSupported(_))
+        ));
+    }
+}
@@ -0,0 +1,297 @@
+//! The poll module.
+//!
+//! This module contains the `PollHandle`, which is returned
+//! when moving a `Spotify` instance to a polling thread.
+
+use crate::status::{SimpleTrack, SpotifyStatus};
+use crate::ConnectionState;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle, Thread};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// The name of polling threads
+const THREAD_NAME: &str = "spotify-poll";
+
+/// A handle to a polling thread.
+pub struct PollHandle {
+    /// The join handle of the polling thread.
+    handle: JoinHandle<()>,
+    /// The counters updated by the polling thread.
+    counters: Arc<PollCounters>,
+    /// The connection state of the polled client.
+    state: Arc<Mutex<ConnectionState>>,
+}
+
+/// Options for polling the client status.
+#[derive(Debug, Clone)]
+pub struct PollOptions {
+    /// The polling interval.
+    pub(crate) interval: Duration,
+    /// Whether to skip the initial callback.
+    pub(crate) suppress_initial: bool,
+    /// The number of recently played tracks to remember.
+    pub(crate) history_capacity: usize,
+}
+
+/// Implements `Default` for `PollOptions`.
+impl Default for PollOptions {
+    fn default() -> PollOptions {
+        PollOptions {
+            interval: Duration::from_millis(250),
+            suppress_initial: false,
+            history_capacity: 0,
+        }
+    }
+}
+
+/// Implements `PollOptions`.
+impl PollOptions {
+    /// Constructs new `PollOptions` with default settings.
+    pub fn new() -> PollOptions {
+        PollOptions::default()
+    }
+    /// Sets the polling interval.
+    /// Defaults to 250ms.
+    pub fn interval(mut self, interval: Duration) -> PollOptions {
+        self.interval = interval;
+        self
+    }
+    /// Sets whether to skip the initial callback, which reports
+    /// all fields as changed. Defaults to false.
+    ///
+    /// If set, the closure is only called on genuine changes, so the
+    /// initial status is never pushed. Use `Spotify::status` to get it.
+    pub fn suppress_initial(mut self, suppress_initial: bool) -> PollOptions {
+        self.suppress_initial = suppress_initial;
+        self
+    }
+    /// Sets the number of recently played tracks to remember,
+    /// which are available from `PollHandle::history`.
+    /// Defaults to zero, which disables the history.
+    pub fn history_capacity(mut self, capacity: usize) -> PollOptions {
+        self.history_capacity = capacity;
+        self
+    }
+}
+
+/// A snapshot of the statistics of a polling thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PollStats {
+    /// The number of polling iterations.
+    pub ticks: u64,
+    /// The number of successful status fetches.
+    pub successful_fetches: u64,
+    /// The number of failed status fetches.
+    pub failed_fetches: u64,
+    /// The time of the last failed status fetch.
+    pub last_error: Option<SystemTime>,
+}
+
+/// The counters and the latest status updated by a polling thread.
+#[derive(Debug, Default)]
+pub(crate) struct PollCounters {
+    /// The number of polling iterations.
+    ticks: AtomicU64,
+    /// The number of successful status fetches.
+    successful_fetches: AtomicU64,
+    /// The number of failed status fetches.
+    failed_fetches: AtomicU64,
+    /// The time of the last failed status fetch,
+    /// in milliseconds since the unix epoch.
+    /// Zero if no fetch has failed yet.
+    last_error: AtomicU64,
+    /// The most recently fetched status.
+    last_status: Mutex<Option<SpotifyStatus>>,
+    /// The recently played tracks together with their uri, oldest first.
+    history: Mutex<VecDeque<(String, SimpleTrack)>>,
+    /// The number of recently played tracks to remember.
+    history_capacity: usize,
+    /// Whether the polling thread was asked to stop.
+    stopped: AtomicBool,
+}
+
+/// Implements `PollCounters`.
+impl PollCounters {
+    /// Constructs new `PollCounters` remembering
+    /// the specified number of recently played tracks.
+    pub fn new(history_capacity: usize) -> PollCounters {
+        PollCounters {
+            history_capacity,
+            ..PollCounters::default()
+        }
+    }
+    /// Tests whether the polling thread was asked to stop.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+    /// Records a polling iteration.
+    pub fn tick(&self) {
+        self.ticks.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Records a successful fetch of the specified status.
+    pub fn success(&self, status: &SpotifyStatus) {
+        self.successful_fetches.fetch_add(1, Ordering::Relaxed);
+        *self.last_status.lock().unwrap() = Some(status.clone());
+        self.record_track(status);
+    }
+    /// Appends the track of the specified status to the history,
+    /// unless it is the same as the last one, by uri.
+    /// Drops the oldest track once the capacity is exceeded.
+    fn record_track(&self, status: &SpotifyStatus) {
+        if self.history_capacity == 0 {
+            return;
+        }
+        let track = match status.try_track() {
+            Some(track) => track,
+            None => return,
+        };
+        let uri = status.track_ref().track.uri.clone();
+        let mut history = self.history.lock().unwrap();
+        if history.back().map(|entry| &entry.0) == Some(&uri) {
+            return;
+        }
+        if history.len() == self.history_capacity {
+            history.pop_front();
+        }
+        history.push_back((uri, track));
+    }
+    /// Records a failed status fetch.
+    pub fn failure(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis() as u64)
+            .unwrap_or(0);
+        self.failed_fetches.fetch_add(1, Ordering::Relaxed);
+        self.last_error.store(now.max(1), Ordering::Relaxed);
+    }
+}
+
+/// Implements `PollHandle`.
+impl PollHandle {
+    /// Spawns a new polling thread named `spotify-poll`,
+    /// running the specified closure with the specified counters.
+    pub(crate) fn spawn<F>(
+        state: Arc<Mutex<ConnectionState>>,
+        counters: PollCounters,
+        f: F,
+    ) -> io::Result<PollHandle>
+    where
+        F: FnOnce(&PollCounters),
+        F: std::marker::Send + 'static,
+    {
+        let counters = Arc::new(counters);
+        let thread_counters = counters.clone();
+        let handle = thread::Builder::new()
+            .name(THREAD_NAME.to_owned())
+            .spawn(move || f(&thread_counters))?;
+        Ok(PollHandle {
+            handle,
+            counters,
+            state,
+        })
+    }
+    /// Gets the polling thread.
+    pub fn thread(&self) -> &Thread {
+        self.handle.thread()
+    }
+    /// Gets the connection state of the polled client.
+    pub fn state(&self) -> ConnectionState {
+        self.state.lock().unwrap().clone()
+    }
+    /// Gets the current statistics of the polling thread.
+    /// Can be called at any time without interrupting the polling.
+    pub fn stats(&self) -> PollStats {
+        let last_error = self.counters.last_error.load(Ordering::Relaxed);
+        PollStats {
+            ticks: self.counters.ticks.load(Ordering::Relaxed),
+            successful_fetches: self.counters.successful_fetches.load(Ordering::Relaxed),
+            failed_fetches: self.counters.failed_fetches.load(Ordering::Relaxed),
+            last_error: match last_error {
+                0 => None,
+                millis => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+            },
+        }
+    }
+    /// Gets the most recently polled status, or `None`
+    /// if no status has been fetched successfully yet.
+    /// Can be called at any time without issuing a request.
+    pub fn last_status(&self) -> Option<SpotifyStatus> {
+        self.counters.last_status.lock().unwrap().clone()
+    }
+    /// Gets the recently played tracks, oldest first.
+    /// Empty unless enabled using `PollOptions::history_capacity`.
+    pub fn history(&self) -> Vec<SimpleTrack> {
+        let history = self.counters.history.lock().unwrap();
+        history.iter().map(|entry| entry.1.clone()).collect()
+    }
+    /// Asks the polling thread to stop before its next iteration,
+    /// as if the closure had returned false. Use `join` to wait
+    /// for it to finish.
+    pub fn stop(&self) {
+        self.counters.stopped.store(true, Ordering::Relaxed);
+    }
+    /// Waits for the polling thread to finish.
+    /// Returns an error if the polling thread panicked.
+    pub fn join(self) -> thread::Result<()> {
+        self.handle.join()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PollCounters, PollHandle};
+    use crate::status::SpotifyStatus;
+    use crate::test_server::STATUS;
+    use crate::ConnectionState;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn stats_count_ticks_and_fetches() {
+        let state = Arc::new(Mutex::new(ConnectionState::Connected));
+        let status = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        let polled = status.clone();
+        let handle = PollHandle::spawn(state, PollCounters::default(), move |counters| {
+            for _ in 0..3 {
+                counters.tick();
+                counters.success(&polled);
+            }
+            counters.tick();
+            counters.failure();
+        })
+        .unwrap();
+        assert_eq!(handle.thread().name(), Some("spotify-poll"));
+        while !handle.handle.is_finished() {
+            std::thread::yield_now();
+        }
+        let stats = handle.stats();
+        assert_eq!(stats.ticks, 4);
+        assert_eq!(stats.successful_fetches, 3);
+        assert_eq!(stats.failed_fetches, 1);
+        assert!(stats.last_error.is_some());
+        assert_eq!(handle.last_status(), Some(status));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn history_keeps_the_latest_track_changes() {
+        let counters = PollCounters::new(2);
+        for uri in ["a", "a", "b", "c", "c"].iter() {
+            let mut json = json::parse(STATUS).unwrap();
+            json["track"]["track_resource"]["uri"] = format!("spotify:track:{}", uri).into();
+            json["track"]["track_resource"]["name"] = uri.to_string().into();
+            counters.success(&SpotifyStatus::from(json));
+        }
+        let state = Arc::new(Mutex::new(ConnectionState::Connected));
+        let handle = PollHandle::spawn(state, counters, |_| {}).unwrap();
+        let names: Vec<_> = handle
+            .history()
+            .into_iter()
+            .map(|track| track.name)
+            .collect();
+        assert_eq!(names, ["b", "c"]);
+        handle.join().unwrap();
+    }
+}
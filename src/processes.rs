@@ -0,0 +1,169 @@
+//! The processes module.
+//!
+//! This module finds running processes by name on all platforms,
+//! e.g. to enumerate several running Spotify clients.
+//!
+//! Linux reads `/proc`, other Unix systems run `ps`, and Windows
+//! takes a process snapshot (requires the `windows-process` feature).
+//! On other platforms, no processes are found.
+
+/// A running process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessInfo {
+    /// The process id.
+    pub pid: u32,
+    /// The process name, e.g. `spotify` or `Spotify.exe`.
+    pub name: String,
+}
+
+/// Finds all running processes with the specified name.
+///
+/// On Windows, the `.exe` extension may be omitted and the name
+/// is compared case-insensitively. If the processes cannot be
+/// enumerated, a warning is logged and none are found.
+pub fn find_all(name: &str) -> impl Iterator<Item = ProcessInfo> {
+    let processes = match backend::find_all(name) {
+        Ok(processes) => processes,
+        Err(error) => {
+            warn!("Unable to enumerate processes: {}", error);
+            Vec::new()
+        }
+    };
+    processes.into_iter()
+}
+
+#[cfg(target_os = "linux")]
+mod backend {
+    use super::ProcessInfo;
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    // The length at which the kernel truncates process names
+    const COMM_MAX_LEN: usize = 15;
+
+    /// Finds all processes with the specified name in `/proc`.
+    pub fn find_all(name: &str) -> io::Result<Vec<ProcessInfo>> {
+        let mut processes = Vec::new();
+        for entry in fs::read_dir("/proc")? {
+            let path = entry?.path();
+            let pid = match path.file_name().and_then(|pid| pid.to_str()?.parse().ok()) {
+                Some(pid) => pid,
+                None => continue,
+            };
+            // The process may have exited since listing the directory.
+            if let Some(comm) = process_name(&path) {
+                if comm == name {
+                    processes.push(ProcessInfo { pid, name: comm });
+                }
+            }
+        }
+        Ok(processes)
+    }
+
+    /// Gets the name of the process at the specified `/proc` path.
+    /// Truncated names are completed from the command line.
+    fn process_name(path: &Path) -> Option<String> {
+        let comm = fs::read_to_string(path.join("comm")).ok()?;
+        let comm = comm.trim_end_matches('\n').to_owned();
+        if comm.len() < COMM_MAX_LEN {
+            return Some(comm);
+        }
+        let cmdline = fs::read(path.join("cmdline")).ok()?;
+        let program = cmdline.split(|c| *c == 0).next()?;
+        let program = Path::new(std::str::from_utf8(program).ok()?).file_name()?;
+        match program.to_str() {
+            Some(program) if program.starts_with(&comm) => Some(program.to_owned()),
+            _ => Some(comm),
+        }
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+mod backend {
+    use super::ProcessInfo;
+    use std::io;
+    use std::path::Path;
+    use std::process::Command;
+
+    /// Finds all processes with the specified name using `ps`.
+    pub fn find_all(name: &str) -> io::Result<Vec<ProcessInfo>> {
+        let output = Command::new("ps")
+            .args(["-A", "-o", "pid=,comm="])
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other("ps failed"));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let (pid, command) = line.trim_start().split_once(' ')?;
+                // Some systems report the full path of the executable.
+                let command = Path::new(command.trim()).file_name()?.to_str()?;
+                Some(ProcessInfo {
+                    pid: pid.parse().ok()?,
+                    name: command.to_owned(),
+                })
+            })
+            .filter(|process| process.name == name)
+            .collect())
+    }
+}
+
+#[cfg(all(windows, feature = "windows-process"))]
+mod backend {
+    use super::ProcessInfo;
+    use crate::windows_process::WindowsProcess;
+    use std::io;
+
+    /// Finds all processes with the specified name in a process snapshot.
+    pub fn find_all(name: &str) -> io::Result<Vec<ProcessInfo>> {
+        let lower = name.to_lowercase();
+        let exe_name = if lower.ends_with(".exe") {
+            lower
+        } else {
+            format!("{}.exe", lower)
+        };
+        Ok(
+            WindowsProcess::find_all_by(|name| name.to_lowercase() == exe_name)?
+                .iter()
+                .map(|process| ProcessInfo {
+                    pid: process.pid(),
+                    name: process.name().to_owned(),
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(not(any(unix, all(windows, feature = "windows-process"))))]
+mod backend {
+    use super::ProcessInfo;
+    use std::io;
+
+    /// Finds no processes, since the platform is not supported.
+    pub fn find_all(_name: &str) -> io::Result<Vec<ProcessInfo>> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::find_all;
+    use std::process;
+
+    #[test]
+    fn find_all_finds_the_current_process() {
+        let exe = std::env::current_exe().unwrap();
+        let name = exe.file_name().unwrap().to_str().unwrap().to_owned();
+        let processes: Vec<_> = find_all(&name).collect();
+        assert!(processes
+            .iter()
+            .any(|p| p.pid == process::id() && p.name == name));
+    }
+
+    #[test]
+    fn find_all_ignores_unknown_names() {
+        assert_eq!(find_all("no-such-process-name").count(), 0);
+    }
+}
@@ -6,9 +6,18 @@
 //! It also contains some extra abstractions, such as the `SimpleTrack` struct.
 
 use json::JsonValue;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use time::{self, Timespec, Tm};
 
+// The latest representable server time (9999-12-31T23:59:59Z)
+const MAX_SERVER_TIME: i64 = 253_402_300_799;
+
 /// A change in the Spotify status.
+#[derive(Debug, Clone)]
 pub struct SpotifyStatusChange {
     /// Indicates a change in the volume.
     pub volume: bool,
@@ -56,6 +65,8 @@ pub struct SpotifyStatus {
     playing: bool,
     /// Whether shuffle mode is activated.
     shuffle: bool,
+    /// Whether repeat mode is activated.
+    repeat: bool,
     /// The server time as a unix timestamp.
     server_time: i64,
     /// Whether playing a track is enabled.
@@ -72,6 +83,9 @@ pub struct SpotifyStatus {
     open_graph_state: OpenGraphState,
     /// The currently playing track.
     track: Track,
+    /// The uri of the context the track is played from,
+    /// e.g. a playlist or an album.
+    context_uri: Option<String>,
 }
 
 /// A Spotify Open Graph state.
@@ -84,18 +98,36 @@ struct OpenGraphState {
 }
 
 /// A Spotify track.
+///
+/// Fields may be added in minor versions, so a `Track` can only
+/// be constructed by parsing it, e.g. using `Track::from`.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct Track {
     /// The track.
     pub track: Resource,
     /// The album.
     pub album: Resource,
     /// The artist.
+    /// This is the primary artist if there are several.
     pub artist: Resource,
+    /// All artists, the primary artist first.
+    pub artists: Vec<Resource>,
     /// The length in full seconds.
+    /// Prefer `Track::length_secs`, which never goes negative.
     pub length: i32,
-    /// The track type.
+    /// The uri of the playing item as reported by the client.
+    pub uri: String,
+    /// The uri of the playing item as reported by the client.
+    /// Despite its name, this has never held a track type.
+    #[deprecated(note = "holds the item uri, use `Track::uri` instead")]
     pub track_type: String,
+    /// Whether the track is explicit.
+    /// Older clients do not provide this and always report `false`.
+    pub explicit: bool,
+    /// Whether the track is an advertisement,
+    /// i.e. its type is `ad` or its uri is a `spotify:ad:` uri.
+    pub ad: bool,
 }
 
 /// A Spotify resource.
@@ -126,9 +158,49 @@ pub struct SimpleTrack {
     /// The album name.
     pub album: String,
     /// The artist name.
+    /// Multiple artists are joined with `, `.
     pub artist: String,
 }
 
+/// What the client is currently doing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlaybackState {
+    /// A track is playing.
+    Playing(SimpleTrack),
+    /// A track is loaded, but paused.
+    Paused(SimpleTrack),
+    /// No track is loaded in the client.
+    Idle,
+}
+
+/// The progress of the currently loaded track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    /// The reported playing position.
+    pub position: Duration,
+    /// The length of the track.
+    pub length: Duration,
+    /// Whether the track is playing.
+    pub playing: bool,
+    /// The fraction of the track played, from `0.0` to `1.0`.
+    /// Zero if the length of the track is unknown.
+    pub fraction: f32,
+}
+
+/// A Spotify status together with diagnostic information
+/// about how it was retrieved.
+#[derive(Debug, Clone)]
+pub struct StatusDiagnostic {
+    /// The raw JSON response body.
+    pub raw_json: String,
+    /// The time it took to receive the response.
+    pub http_latency: Duration,
+    /// The time it took to parse the response.
+    pub parse_time: Duration,
+    /// The parsed status.
+    pub status: SpotifyStatus,
+}
+
 /// Transforms a JSON value into an owned String.
 #[inline]
 fn get_json_str(json: &JsonValue) -> String {
@@ -138,6 +210,37 @@ fn get_json_str(json: &JsonValue) -> String {
     }
 }
 
+/// Parses a number some clients send as a JSON string, e.g. `"0.5"`.
+#[inline]
+fn parse_json_str<T: FromStr>(json: &JsonValue) -> Option<T> {
+    json.as_str()?.trim().parse().ok()
+}
+
+/// Transforms a JSON value into a finite f32.
+/// Values out of the range of f32 yield zero.
+/// Numbers given as strings are parsed as well.
+#[inline]
+fn get_json_f32(json: &JsonValue) -> f32 {
+    json.as_f32()
+        .or_else(|| parse_json_str(json))
+        .filter(|val| val.is_finite())
+        .unwrap_or(0_f32)
+}
+
+/// Transforms a JSON value into an i32.
+/// Numbers given as strings are parsed as well.
+#[inline]
+fn get_json_i32(json: &JsonValue) -> Option<i32> {
+    json.as_i32().or_else(|| parse_json_str(json))
+}
+
+/// Transforms a JSON value into an i64.
+/// Numbers given as strings are parsed as well.
+#[inline]
+fn get_json_i64(json: &JsonValue) -> Option<i64> {
+    json.as_i64().or_else(|| parse_json_str(json))
+}
+
 /// Implements `SpotifyStatus`.
 impl SpotifyStatus {
     /// Gets an easy-to-work-with abstraction over
@@ -146,15 +249,51 @@ impl SpotifyStatus {
     pub fn track(&self) -> SimpleTrack {
         SimpleTrack::from(&self.track)
     }
+    /// Gets an easy-to-work-with abstraction over
+    /// the currently playing track, or `None` if
+    /// no track is loaded in the client.
+    pub fn try_track(&self) -> Option<SimpleTrack> {
+        if self.track.track.name.is_empty() && self.track.track.uri.is_empty() {
+            return None;
+        }
+        Some(SimpleTrack::from(&self.track))
+    }
+    /// Gets the artist and the title of the currently playing track,
+    /// or `None` if no track is playing.
+    pub fn artist_and_title(&self) -> Option<(String, String)> {
+        match self.playback_state() {
+            PlaybackState::Playing(track) => Some((track.artist, track.name)),
+            _ => None,
+        }
+    }
+    /// Gets what the client is currently doing.
+    /// Unlike `is_playing`, this tells apart a paused
+    /// track from a client with no track loaded at all.
+    pub fn playback_state(&self) -> PlaybackState {
+        match self.try_track() {
+            Some(track) if self.playing => PlaybackState::Playing(track),
+            Some(track) => PlaybackState::Paused(track),
+            None => PlaybackState::Idle,
+        }
+    }
     /// Gets all information regarding the
     /// currently playing track.
     pub fn full_track(&self) -> Track {
         self.track.clone()
     }
+    /// Gets all information regarding the
+    /// currently playing track without cloning it.
+    pub fn track_ref(&self) -> &Track {
+        &self.track
+    }
     /// Gets the client version.
     pub fn version(&self) -> String {
         self.client_version.clone()
     }
+    /// Gets the client version without cloning it.
+    pub fn client_version_str(&self) -> &str {
+        &self.client_version
+    }
     /// Gets the volume.
     /// Possible values range from `0.0_f32` to `1.0_f32`.
     pub fn volume(&self) -> f32 {
@@ -162,25 +301,148 @@ impl SpotifyStatus {
     }
     /// Gets the volume as percentage.
     /// Possible values range from `0.0_f32` to `100.0_f32`.
+    ///
+    /// The percentage is truncated, so a volume of `0.999` yields `99.0`.
+    /// Use `volume_percentage_rounded` for display purposes.
     pub fn volume_percentage(&self) -> f32 {
         (self.volume * 100_f32).trunc()
     }
+    /// Gets the volume as rounded percentage.
+    /// Possible values range from `0` to `100`.
+    pub fn volume_percentage_rounded(&self) -> u8 {
+        (self.volume * 100_f32).round().clamp(0_f32, 100_f32) as u8
+    }
     /// Gets the server timestamp.
     pub fn timestamp(&self) -> i64 {
         self.server_time
     }
+    /// Gets the server time, or `None` if it lies before the epoch,
+    /// after the year 9999 or can't be represented as a `SystemTime`.
+    pub fn server_time_checked(&self) -> Option<SystemTime> {
+        if self.server_time > MAX_SERVER_TIME {
+            return None;
+        }
+        let secs = u64::try_from(self.server_time).ok()?;
+        UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+    }
+    /// Gets the server time as a `Timespec`,
+    /// falling back to the epoch if it is out of range.
+    fn server_timespec(&self) -> Timespec {
+        let secs = self
+            .server_time_checked()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |time| time.as_secs() as i64);
+        Timespec::new(secs, 0)
+    }
     /// Gets the local server time.
+    /// Falls back to the epoch if the server time is out of range.
     pub fn time(&self) -> Tm {
-        time::at(Timespec::new(self.server_time, 0))
+        time::at(self.server_timespec())
     }
     /// Gets the coordinated universal server time.
+    /// Falls back to the epoch if the server time is out of range.
     pub fn time_utc(&self) -> Tm {
-        time::at_utc(Timespec::new(self.server_time, 0))
+        time::at_utc(self.server_timespec())
+    }
+    /// Gets the wall clock time elapsed since the server time,
+    /// i.e. how stale the status is.
+    ///
+    /// Saturates at zero if the server time lies in the future,
+    /// e.g. due to clock skew. The resolution is one second.
+    pub fn elapsed_since_server_time(&self) -> Duration {
+        let server_time = self.server_time_checked().unwrap_or(UNIX_EPOCH);
+        SystemTime::now()
+            .duration_since(server_time)
+            .unwrap_or(Duration::ZERO)
+    }
+    /// Gets the playing position in seconds.
+    pub fn playing_position(&self) -> f32 {
+        self.playing_position
+    }
+    /// Gets the progress of the current track,
+    /// or `None` if no track is loaded in the client.
+    ///
+    /// The position is the reported one. Use `estimated_position`
+    /// to account for the time elapsed since the status was fetched.
+    pub fn progress(&self) -> Option<Progress> {
+        self.try_track()?;
+        let position = self.playing_position.max(0_f32);
+        let length = self.track.length_secs();
+        let fraction = match length {
+            0 => 0_f32,
+            length => (position / length as f32).min(1_f32),
+        };
+        Some(Progress {
+            position: Duration::from_secs_f32(position),
+            length: Duration::from_secs(u64::from(length)),
+            playing: self.playing,
+            fraction,
+        })
+    }
+    /// Tests whether the current track should be scrobbled.
+    ///
+    /// A track counts once it has played for at least `min_seconds`,
+    /// or at least `min_fraction` of its length if that is known,
+    /// i.e. `position >= min_seconds || position >= min_fraction * length`.
+    /// The reported playing position is used. Advertisements and
+    /// clients without a loaded track never count.
+    pub fn is_scrobbleable(&self, min_seconds: u32, min_fraction: f32) -> bool {
+        if self.try_track().is_none() || self.track.is_ad() {
+            return false;
+        }
+        let position = self.playing_position;
+        let length = self.track.length_secs();
+        position >= min_seconds as f32 || (length > 0 && position >= min_fraction * length as f32)
+    }
+    /// Gets the playing position in milliseconds.
+    ///
+    /// The client reports the position as fractional seconds, which
+    /// are rounded to the nearest millisecond here. The position is
+    /// only as fresh as the status though, and `server_time` only has
+    /// a resolution of one second, so it can't be used to refine it.
+    pub fn playing_position_ms(&self) -> u64 {
+        (f64::from(self.playing_position) * 1000_f64)
+            .round()
+            .max(0_f64) as u64
+    }
+    /// Gets the estimated current playing position in seconds.
+    ///
+    /// If a track is playing, the reported position is advanced by the
+    /// wall clock time elapsed since the server time of the status, and
+    /// clamped to the length of the track. Since the server time only has
+    /// a resolution of one second, so does the correction.
+    pub fn estimated_position(&self) -> f32 {
+        if !self.playing || self.server_time == 0 {
+            return self.playing_position;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs() as i64)
+            .unwrap_or(self.server_time);
+        let elapsed = (now - self.server_time).max(0) as f32;
+        let position = self.playing_position + elapsed;
+        match self.track.length_secs() {
+            0 => position,
+            length => position.min(length as f32),
+        }
+    }
+    /// Gets a value indicating whether a track is currently playing.
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+    /// Gets a value indicating whether playing is enabled.
+    /// If not, a UI should disable its play control.
+    pub fn play_enabled(&self) -> bool {
+        self.play_enabled
     }
     /// Gets a value indicating whether shuffling is enabled.
     pub fn shuffle_enabled(&self) -> bool {
         self.shuffle
     }
+    /// Gets a value indicating whether repeating is enabled.
+    pub fn repeat_enabled(&self) -> bool {
+        self.repeat
+    }
     /// Gets a value indicating whether the client is
     /// currently connected to the Internet.
     pub fn is_online(&self) -> bool {
@@ -191,6 +453,142 @@ impl SpotifyStatus {
     pub fn is_private_session(&self) -> bool {
         self.open_graph_state.private_session
     }
+    /// Gets the uri of the context the current track
+    /// is played from, e.g. a playlist or an album.
+    /// Returns `None` if the client doesn't report a context.
+    pub fn context_uri(&self) -> Option<String> {
+        self.context_uri.clone()
+    }
+    /// Formats the status using the specified template.
+    ///
+    /// The following placeholders are replaced:
+    /// `{track}`, `{artist}`, `{album}`, `{uri}`, `{position}` and
+    /// `{length}` (both as `m:ss`), and `{volume}` (as percentage).
+    /// All other text is copied verbatim.
+    pub fn format(&self, template: &str) -> String {
+        let track = self.track();
+        let minutes = |secs: u64| format!("{}:{:02}", secs / 60, secs % 60);
+        let mut output = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            output.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = match rest.find('}') {
+                Some(end) => end,
+                None => break,
+            };
+            match &rest[1..end] {
+                "track" => output.push_str(&track.name),
+                "artist" => output.push_str(&track.artist),
+                "album" => output.push_str(&track.album),
+                "uri" => output.push_str(&self.track.track.uri),
+                "position" => output.push_str(&minutes(self.playing_position_ms() / 1000)),
+                "length" => output.push_str(&minutes(u64::from(self.track.length_secs()))),
+                "volume" => output.push_str(&self.volume_percentage_rounded().to_string()),
+                _ => output.push_str(&rest[..=end]),
+            }
+            rest = &rest[end + 1..];
+        }
+        output.push_str(rest);
+        output
+    }
+    /// Formats the status using the specified template (see `format`)
+    /// and writes it to the specified file.
+    ///
+    /// The file is written atomically, by writing to a temporary file
+    /// in the same directory first, which then replaces the file.
+    /// Readers thus never see a partially written file.
+    pub fn write_to_file(&self, path: &Path, template: &str) -> io::Result<()> {
+        let file_name = match path.file_name() {
+            Some(file_name) => file_name.to_string_lossy(),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "not a file path",
+                ))
+            }
+        };
+        let temp_path = path.with_file_name(format!(".{}.tmp", file_name));
+        let result = fs::File::create(&temp_path).and_then(|mut file| {
+            file.write_all(self.format(template).as_bytes())?;
+            file.sync_all()
+        });
+        match result.and_then(|_| fs::rename(&temp_path, path)) {
+            Ok(()) => Ok(()),
+            Err(error) => {
+                let _ = fs::remove_file(&temp_path);
+                Err(error)
+            }
+        }
+    }
+    /// Compares two statuses, ignoring the server time
+    /// and the playing position, which change constantly.
+    ///
+    /// Unlike `==`, this tells whether two statuses
+    /// taken at different moments describe the same state.
+    pub fn content_eq(&self, other: &SpotifyStatus) -> bool {
+        self.volume == other.volume
+            && self.online == other.online
+            && self.version == other.version
+            && self.running == other.running
+            && self.playing == other.playing
+            && self.shuffle == other.shuffle
+            && self.repeat == other.repeat
+            && self.play_enabled == other.play_enabled
+            && self.prev_enabled == other.prev_enabled
+            && self.next_enabled == other.next_enabled
+            && self.client_version == other.client_version
+            && self.open_graph_state == other.open_graph_state
+            && self.track == other.track
+            && self.context_uri == other.context_uri
+    }
+}
+
+/// Implements `Track`.
+impl Track {
+    /// Gets all artists of the track.
+    /// The primary artist comes first.
+    pub fn artists(&self) -> Vec<Resource> {
+        self.artists.clone()
+    }
+    /// Gets the length of the track in full seconds.
+    /// Negative lengths are reported as zero.
+    pub fn length_secs(&self) -> u32 {
+        self.length.max(0) as u32
+    }
+    /// Tests whether the track is an advertisement,
+    /// i.e. its type is `ad` or its uri is a `spotify:ad:` uri.
+    pub fn is_ad(&self) -> bool {
+        self.ad
+    }
+    /// Tests whether the track is explicit.
+    /// Older clients do not provide this and always report `false`.
+    pub fn is_explicit(&self) -> bool {
+        self.explicit
+    }
+    /// Tests whether both tracks are the same song,
+    /// comparing the track resource uri only.
+    ///
+    /// Unlike `==`, this ignores fields that may differ between
+    /// reads of the same song, such as the length.
+    pub fn same_song_as(&self, other: &Track) -> bool {
+        self.track.uri == other.track.uri
+    }
+}
+
+/// Implements `Resource`.
+impl Resource {
+    /// Gets the `https://open.spotify.com` web url of the resource,
+    /// e.g. `spotify:track:<id>` becomes `https://open.spotify.com/track/<id>`.
+    /// Falls back to the open graph url if the uri is malformed.
+    pub fn web_url(&self) -> String {
+        match self.uri.strip_prefix("spotify:") {
+            Some(path) if !path.is_empty() && path.split(':').all(|part| !part.is_empty()) => {
+                format!("https://open.spotify.com/{}", path.replace(':', "/"))
+            }
+            _ => self.location.og.clone(),
+        }
+    }
 }
 
 /// Implements `SpotifyStatusChange`.
@@ -214,27 +612,252 @@ impl SpotifyStatusChange {
             track: true,
         }
     }
+    /// Constructs a new `SpotifyStatusChange` with all fields set to false.
+    pub fn new_false() -> SpotifyStatusChange {
+        SpotifyStatusChange {
+            volume: false,
+            online: false,
+            version: false,
+            running: false,
+            playing: false,
+            shuffle: false,
+            server_time: false,
+            play_enabled: false,
+            prev_enabled: false,
+            next_enabled: false,
+            client_version: false,
+            playing_position: false,
+            open_graph_state: false,
+            track: false,
+        }
+    }
+    /// Constructs a new `SpotifyStatusChangeBuilder`,
+    /// starting with all fields set to false.
+    ///
+    /// Useful to test callbacks against specific changes:
+    ///
+    /// ```
+    /// # use spotify::status::SpotifyStatusChange;
+    /// let change = SpotifyStatusChange::builder().track(true).volume(true).build();
+    /// assert_eq!(change.changed_fields(), vec!["volume", "track"]);
+    /// ```
+    pub fn builder() -> SpotifyStatusChangeBuilder {
+        SpotifyStatusChangeBuilder {
+            change: SpotifyStatusChange::new_false(),
+        }
+    }
+    /// Compares the current status with the last status
+    /// and indicates which fields have changed.
+    pub fn between(curr: &SpotifyStatus, last: &SpotifyStatus) -> SpotifyStatusChange {
+        macro_rules! status_compare_field {
+            ($field:ident) => {
+                curr.$field != last.$field
+            };
+        }
+        SpotifyStatusChange {
+            volume: status_compare_field!(volume),
+            online: status_compare_field!(online),
+            version: status_compare_field!(version),
+            running: status_compare_field!(running),
+            playing: status_compare_field!(playing),
+            shuffle: status_compare_field!(shuffle),
+            server_time: status_compare_field!(server_time),
+            play_enabled: status_compare_field!(play_enabled),
+            prev_enabled: status_compare_field!(prev_enabled),
+            next_enabled: status_compare_field!(next_enabled),
+            client_version: status_compare_field!(client_version),
+            playing_position: status_compare_field!(playing_position),
+            open_graph_state: status_compare_field!(open_graph_state),
+            track: status_compare_field!(track),
+        }
+    }
+    /// Merges the specified change into this one,
+    /// so that each field indicates a change in either.
+    /// Useful to accumulate changes across several polls.
+    pub fn merge(&mut self, other: &SpotifyStatusChange) {
+        macro_rules! change_merge_field {
+            ($($field:ident),*) => {
+                $(self.$field |= other.$field;)*
+            };
+        }
+        change_merge_field!(
+            volume,
+            online,
+            version,
+            running,
+            playing,
+            shuffle,
+            server_time,
+            play_enabled,
+            prev_enabled,
+            next_enabled,
+            client_version,
+            playing_position,
+            open_graph_state,
+            track
+        );
+    }
+    /// Gets a value indicating whether a field a human
+    /// would notice has changed (track, volume, playing state,
+    /// shuffle mode or online status).
+    ///
+    /// Changes to `server_time` and `playing_position` are ignored,
+    /// since those change on pretty much every single poll.
+    pub fn is_user_visible(&self) -> bool {
+        self.track || self.volume || self.playing || self.shuffle || self.online
+    }
+    /// Gets the names of all changed fields.
+    pub fn changed_fields(&self) -> Vec<&'static str> {
+        let fields = [
+            ("volume", self.volume),
+            ("online", self.online),
+            ("version", self.version),
+            ("running", self.running),
+            ("playing", self.playing),
+            ("shuffle", self.shuffle),
+            ("server_time", self.server_time),
+            ("play_enabled", self.play_enabled),
+            ("prev_enabled", self.prev_enabled),
+            ("next_enabled", self.next_enabled),
+            ("client_version", self.client_version),
+            ("playing_position", self.playing_position),
+            ("open_graph_state", self.open_graph_state),
+            ("track", self.track),
+        ];
+        fields
+            .iter()
+            .filter(|field| field.1)
+            .map(|field| field.0)
+            .collect()
+    }
+}
+
+/// A builder for a `SpotifyStatusChange`.
+pub struct SpotifyStatusChangeBuilder {
+    /// The change being built.
+    change: SpotifyStatusChange,
+}
+
+/// Implements `SpotifyStatusChangeBuilder`.
+impl SpotifyStatusChangeBuilder {
+    /// Builds the `SpotifyStatusChange`.
+    pub fn build(self) -> SpotifyStatusChange {
+        self.change
+    }
+}
+
+macro_rules! change_builder_fields {
+    ($($field:ident),*) => {
+        /// Implements the field setters for `SpotifyStatusChangeBuilder`.
+        impl SpotifyStatusChangeBuilder {
+            $(
+                #[doc = concat!("Sets whether the `", stringify!($field), "` field has changed.")]
+                pub fn $field(mut self, changed: bool) -> SpotifyStatusChangeBuilder {
+                    self.change.$field = changed;
+                    self
+                }
+            )*
+        }
+    };
+}
+
+change_builder_fields!(
+    volume,
+    online,
+    version,
+    running,
+    playing,
+    shuffle,
+    server_time,
+    play_enabled,
+    prev_enabled,
+    next_enabled,
+    client_version,
+    playing_position,
+    open_graph_state,
+    track
+);
+
+/// Implements `IntoIterator` for `&SpotifyStatusChange`.
+/// Yields the names of all changed fields.
+impl IntoIterator for &SpotifyStatusChange {
+    type Item = &'static str;
+    type IntoIter = ::std::vec::IntoIter<&'static str>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.changed_fields().into_iter()
+    }
+}
+
+/// Parses the volume into the range [0.0...1.0].
+///
+/// Some client versions report the volume as percentage,
+/// so values above `1.0` are interpreted as percentage.
+/// Note that this makes a percentage of `1` indistinguishable
+/// from full volume, which is read as full volume.
+fn get_json_volume(json: &JsonValue) -> f32 {
+    let volume = get_json_f32(json);
+    if volume > 1_f32 {
+        (volume / 100_f32).min(1_f32)
+    } else {
+        volume.max(0_f32)
+    }
 }
 
 /// Implements `From<JsonValue>` for `SpotifyStatus`.
+///
+/// The object is traversed once, matching each key,
+/// instead of looking up every field separately.
 impl From<JsonValue> for SpotifyStatus {
     fn from(json: JsonValue) -> SpotifyStatus {
-        SpotifyStatus {
-            volume: json["volume"].as_f32().unwrap_or(0_f32),
-            online: json["online"] == true,
-            version: json["version"].as_i32().unwrap_or(0_i32),
-            running: json["running"] == true,
-            playing: json["playing"] == true,
-            shuffle: json["shuffle"] == true,
-            server_time: json["server_time"].as_i64().unwrap_or(0_i64),
-            play_enabled: json["play_enabled"] == true,
-            prev_enabled: json["prev_enabled"] == true,
-            next_enabled: json["next_enabled"] == true,
-            client_version: get_json_str(&json["client_version"]),
-            playing_position: json["playing_position"].as_f32().unwrap_or(0_f32),
-            open_graph_state: OpenGraphState::from(&json["open_graph_state"]),
-            track: Track::from(&json["track"]),
+        let mut status = SpotifyStatus {
+            volume: 0_f32,
+            online: false,
+            version: 0_i32,
+            running: false,
+            playing: false,
+            shuffle: false,
+            repeat: false,
+            server_time: 0_i64,
+            play_enabled: false,
+            prev_enabled: false,
+            next_enabled: false,
+            client_version: String::default(),
+            playing_position: 0_f32,
+            open_graph_state: OpenGraphState::from(&JsonValue::Null),
+            track: Track::from(&JsonValue::Null),
+            context_uri: None,
+        };
+        for (key, value) in json.entries() {
+            match key {
+                "volume" => status.volume = get_json_volume(value),
+                "online" => status.online = *value == true,
+                "version" => status.version = get_json_i32(value).unwrap_or(0_i32),
+                "running" => status.running = *value == true,
+                "playing" => status.playing = *value == true,
+                "shuffle" => status.shuffle = *value == true,
+                "repeat" => status.repeat = *value == true,
+                "server_time" => {
+                    status.server_time = get_json_i64(value)
+                        .filter(|time| (0..=MAX_SERVER_TIME).contains(time))
+                        .unwrap_or(0_i64)
+                }
+                "play_enabled" => status.play_enabled = *value == true,
+                "prev_enabled" => status.prev_enabled = *value == true,
+                "next_enabled" => status.next_enabled = *value == true,
+                "client_version" => status.client_version = get_json_str(value),
+                "playing_position" => status.playing_position = get_json_f32(value),
+                "open_graph_state" => status.open_graph_state = OpenGraphState::from(value),
+                "track" => status.track = Track::from(value),
+                "context" => {
+                    status.context_uri = value["uri"]
+                        .as_str()
+                        .filter(|uri| !uri.is_empty())
+                        .map(str::to_owned)
+                }
+                _ => (),
+            }
         }
+        status
     }
 }
 
@@ -252,11 +875,30 @@ impl<'a> From<&'a JsonValue> for OpenGraphState {
 impl<'a> From<&'a JsonValue> for Track {
     fn from(json: &'a JsonValue) -> Track {
         Track {
+            uri: get_json_str(&json["uri"]),
+            #[allow(deprecated)]
             track_type: get_json_str(&json["uri"]),
             track: Resource::from(&json["track_resource"]),
             album: Resource::from(&json["album_resource"]),
             artist: Resource::from(&json["artist_resource"]),
-            length: json["length"].as_i32().unwrap_or(0_i32),
+            artists: {
+                let artists = &json["artist_resources"];
+                let artists: Vec<Resource> = if artists.is_array() {
+                    artists.members().map(Resource::from).collect()
+                } else {
+                    vec![Resource::from(&json["artist_resource"])]
+                };
+                artists
+                    .into_iter()
+                    .filter(|artist| !artist.name.is_empty() || !artist.uri.is_empty())
+                    .collect()
+            },
+            length: get_json_i32(&json["length"]).unwrap_or(0_i32),
+            explicit: json["track_resource"]["explicit"] == true,
+            ad: json["track_type"] == "ad"
+                || json["track_resource"]["uri"]
+                    .as_str()
+                    .is_some_and(|uri| uri.starts_with("spotify:ad:")),
         }
     }
 }
@@ -281,13 +923,92 @@ impl<'a> From<&'a JsonValue> for ResourceLocation {
     }
 }
 
+/// Implements `From<&'a SpotifyStatus>` for `JsonValue`.
+///
+/// The value has the layout of a status response,
+/// so that it can be turned back into an equal status.
+impl<'a> From<&'a SpotifyStatus> for JsonValue {
+    fn from(status: &'a SpotifyStatus) -> JsonValue {
+        let mut context = JsonValue::new_object();
+        if let Some(ref uri) = status.context_uri {
+            context["uri"] = uri.as_str().into();
+        }
+        json::object! {
+            "volume": status.volume,
+            "online": status.online,
+            "version": status.version,
+            "running": status.running,
+            "playing": status.playing,
+            "shuffle": status.shuffle,
+            "repeat": status.repeat,
+            "server_time": status.server_time,
+            "play_enabled": status.play_enabled,
+            "prev_enabled": status.prev_enabled,
+            "next_enabled": status.next_enabled,
+            "client_version": status.client_version.as_str(),
+            "playing_position": status.playing_position,
+            "open_graph_state": JsonValue::from(&status.open_graph_state),
+            "track": JsonValue::from(&status.track),
+            "context": context,
+        }
+    }
+}
+
+/// Implements `From<&'a OpenGraphState>` for `JsonValue`.
+impl<'a> From<&'a OpenGraphState> for JsonValue {
+    fn from(state: &'a OpenGraphState) -> JsonValue {
+        json::object! {
+            "private_session": state.private_session,
+            "posting_disabled": state.posting_disabled,
+        }
+    }
+}
+
+/// Implements `From<&'a Track>` for `JsonValue`.
+impl<'a> From<&'a Track> for JsonValue {
+    fn from(track: &'a Track) -> JsonValue {
+        let mut json = json::object! {
+            "track_resource": JsonValue::from(&track.track),
+            "album_resource": JsonValue::from(&track.album),
+            "artist_resource": JsonValue::from(&track.artist),
+            "artist_resources": track.artists.iter().map(JsonValue::from).collect::<Vec<_>>(),
+            "length": track.length,
+            "uri": track.uri.as_str(),
+        };
+        json["track_resource"]["explicit"] = track.explicit.into();
+        if track.ad {
+            json["track_type"] = "ad".into();
+        }
+        json
+    }
+}
+
+/// Implements `From<&'a Resource>` for `JsonValue`.
+impl<'a> From<&'a Resource> for JsonValue {
+    fn from(resource: &'a Resource) -> JsonValue {
+        json::object! {
+            "uri": resource.uri.as_str(),
+            "name": resource.name.as_str(),
+            "location": json::object! { "og": resource.location.og.as_str() },
+        }
+    }
+}
+
 /// Implements `From<Track>` for `SimpleTrack`.
 impl<'a> From<&'a Track> for SimpleTrack {
     fn from(track: &'a Track) -> SimpleTrack {
         SimpleTrack {
             name: track.track.name.clone(),
             album: track.album.name.clone(),
-            artist: track.artist.name.clone(),
+            artist: match track.artists.len() {
+                0 => track.artist.name.clone(),
+                _ => track
+                    .artists
+                    .iter()
+                    .map(|artist| artist.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            },
         }
     }
 }
@@ -309,28 +1030,453 @@ impl ::std::fmt::Display for SimpleTrack {
 /// Implements `From<(SpotifyStatus, SpotifyStatus)>` for `SpotifyStatusChange`.
 impl From<(SpotifyStatus, SpotifyStatus)> for SpotifyStatusChange {
     fn from(set: (SpotifyStatus, SpotifyStatus)) -> SpotifyStatusChange {
-        let curr = set.0;
-        let last = set.1;
-        macro_rules! status_compare_field {
-            ($field:ident) => {
-                curr.$field != last.$field
-            };
+        SpotifyStatusChange::between(&set.0, &set.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PlaybackState, Resource, SpotifyStatus, SpotifyStatusChange};
+    use crate::test_server::STATUS;
+    use json::{self, JsonValue};
+    use proptest::prelude::*;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use std::{env, fs, process};
+
+    /// Generates arbitrary JSON values.
+    fn arb_json() -> impl Strategy<Value = JsonValue> {
+        let leaf = prop_oneof![
+            Just(JsonValue::Null),
+            any::<bool>().prop_map(JsonValue::from),
+            any::<i64>().prop_map(JsonValue::from),
+            any::<f64>()
+                .prop_filter("JSON has no NaN or infinity", |n| n.is_finite())
+                .prop_map(JsonValue::from),
+            ".{0,16}".prop_map(JsonValue::from),
+        ];
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..8).prop_map(JsonValue::from),
+                prop::collection::vec(("[a-z_]{1,16}", inner), 0..8).prop_map(|entries| {
+                    let mut object = JsonValue::new_object();
+                    for (key, value) in entries {
+                        object[key] = value;
+                    }
+                    object
+                }),
+            ]
+        })
+    }
+
+    /// Asserts that all accessors of the status work and yield sane values.
+    fn assert_well_formed(status: &SpotifyStatus) {
+        assert!((0_f32..=1_f32).contains(&status.volume()));
+        let _ = status.playing_position_ms();
+        assert!(status.playing_position().is_finite());
+        assert!(status.volume_percentage_rounded() <= 100);
+        let track = status.full_track();
+        assert_eq!(track.length_secs() as i64, i64::from(track.length.max(0)));
+        assert!(track
+            .artists()
+            .iter()
+            .all(|artist| !artist.name.is_empty() || !artist.uri.is_empty()));
+        let _ = (
+            status.track(),
+            status.try_track(),
+            status.version(),
+            status.context_uri(),
+        );
+        let _ = (status.time(), status.time_utc(), status.content_eq(status));
+    }
+
+    #[test]
+    fn progress_bundles_position_and_length() {
+        let status = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        let progress = status.progress().unwrap();
+        assert_eq!(progress.position, Duration::from_millis(42_500));
+        assert_eq!(progress.length, Duration::from_secs(213));
+        assert!(progress.playing);
+        assert!((progress.fraction - 42.5 / 213_f32).abs() < 1e-6);
+        let mut json = json::parse(STATUS).unwrap();
+        json["track"] = JsonValue::new_object();
+        assert_eq!(SpotifyStatus::from(json).progress(), None);
+    }
+
+    #[test]
+    fn is_scrobbleable_checks_seconds_or_fraction() {
+        // 42.5 of 213 seconds have been played.
+        let status = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        assert!(status.is_scrobbleable(30, 0.5));
+        assert!(status.is_scrobbleable(240, 0.1));
+        assert!(!status.is_scrobbleable(240, 0.5));
+        let mut json = json::parse(STATUS).unwrap();
+        json["track"]["track_type"] = "ad".into();
+        let status = SpotifyStatus::from(json);
+        assert!(status.full_track().is_ad());
+        assert!(!status.is_scrobbleable(30, 0.5));
+    }
+
+    #[test]
+    fn is_explicit_defaults_to_false() {
+        let status = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        assert!(!status.full_track().is_explicit());
+        let mut json = json::parse(STATUS).unwrap();
+        json["track"]["track_resource"]["explicit"] = true.into();
+        assert!(SpotifyStatus::from(json).full_track().is_explicit());
+    }
+
+    #[test]
+    fn repeat_is_read_from_the_status() {
+        let status = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        assert!(!status.repeat_enabled());
+        let mut json = json::parse(STATUS).unwrap();
+        json["repeat"] = true.into();
+        assert!(SpotifyStatus::from(json).repeat_enabled());
+    }
+
+    #[test]
+    fn artist_and_title_requires_a_playing_track() {
+        let status = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        assert_eq!(
+            status.artist_and_title(),
+            Some((
+                "Rick Astley".to_owned(),
+                "Never Gonna Give You Up".to_owned()
+            ))
+        );
+        let mut json = json::parse(STATUS).unwrap();
+        json["playing"] = false.into();
+        assert_eq!(SpotifyStatus::from(json).artist_and_title(), None);
+    }
+
+    #[test]
+    fn playback_state_models_idle_clients() {
+        let status = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        assert_eq!(
+            status.playback_state(),
+            PlaybackState::Playing(status.track())
+        );
+        let mut json = json::parse(STATUS).unwrap();
+        json["playing"] = false.into();
+        let status = SpotifyStatus::from(json);
+        assert_eq!(
+            status.playback_state(),
+            PlaybackState::Paused(status.track())
+        );
+        let mut json = json::parse(STATUS).unwrap();
+        json["track"] = JsonValue::new_object();
+        assert_eq!(
+            SpotifyStatus::from(json).playback_state(),
+            PlaybackState::Idle
+        );
+    }
+
+    #[test]
+    fn playing_position_ms_rounds_to_milliseconds() {
+        let status = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        assert_eq!(status.playing_position_ms(), 42_500);
+        let json = json::parse(r#"{"playing_position": 1.2345}"#).unwrap();
+        assert_eq!(SpotifyStatus::from(json).playing_position_ms(), 1_235);
+        let json = json::parse(r#"{"playing_position": -3}"#).unwrap();
+        assert_eq!(SpotifyStatus::from(json).playing_position_ms(), 0);
+    }
+
+    #[test]
+    fn web_url_from_uri() {
+        for (uri, url) in [
+            (
+                "spotify:track:4uLU6hMCjMI75M1A2tKUQC",
+                "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC",
+            ),
+            (
+                "spotify:album:6N9PS4QXF1D0OWPk0Sxtb4",
+                "https://open.spotify.com/album/6N9PS4QXF1D0OWPk0Sxtb4",
+            ),
+            (
+                "spotify:artist:0gxyHStUsqpMadRV0Di1Qt",
+                "https://open.spotify.com/artist/0gxyHStUsqpMadRV0Di1Qt",
+            ),
+            (
+                "spotify:playlist:37i9dQZF1DXcBWIGoYBM5M",
+                "https://open.spotify.com/playlist/37i9dQZF1DXcBWIGoYBM5M",
+            ),
+            (
+                "spotify:user:spotify:playlist:37i9dQZF1DXcBWIGoYBM5M",
+                "https://open.spotify.com/user/spotify/playlist/37i9dQZF1DXcBWIGoYBM5M",
+            ),
+        ]
+        .iter()
+        {
+            let resource = Resource::from(&json::object! { "uri": *uri });
+            assert_eq!(resource.web_url(), *url);
         }
-        SpotifyStatusChange {
-            volume: status_compare_field!(volume),
-            online: status_compare_field!(online),
-            version: status_compare_field!(version),
-            running: status_compare_field!(running),
-            playing: status_compare_field!(playing),
-            shuffle: status_compare_field!(shuffle),
-            server_time: status_compare_field!(server_time),
-            play_enabled: status_compare_field!(play_enabled),
-            prev_enabled: status_compare_field!(prev_enabled),
-            next_enabled: status_compare_field!(next_enabled),
-            client_version: status_compare_field!(client_version),
-            playing_position: status_compare_field!(playing_position),
-            open_graph_state: status_compare_field!(open_graph_state),
-            track: status_compare_field!(track),
+    }
+
+    #[test]
+    fn web_url_falls_back_to_og() {
+        for uri in [
+            "",
+            "spotify:",
+            "track:4uLU6hMCjMI75M1A2tKUQC",
+            "spotify:track:",
+        ]
+        .iter()
+        {
+            let resource = Resource::from(&json::object! {
+                "uri": *uri,
+                "location": { "og": "https://open.spotify.com/track/og" }
+            });
+            assert_eq!(resource.web_url(), "https://open.spotify.com/track/og");
+        }
+    }
+
+    #[test]
+    fn change_between_detects_volume_only_changes() {
+        let last = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        let mut json = json::parse(STATUS).unwrap();
+        json["volume"] = 0.5.into();
+        let curr = SpotifyStatus::from(json);
+        let change = SpotifyStatusChange::between(&curr, &last);
+        assert!(change.volume);
+        assert!(!change.track && !change.playing && !change.playing_position);
+    }
+
+    #[test]
+    fn change_between_detects_track_changes() {
+        let last = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        let mut json = json::parse(STATUS).unwrap();
+        json["track"]["track_resource"]["uri"] = "spotify:track:1pGZIV8olkbRMjyHWoEXyt".into();
+        let curr = SpotifyStatus::from(json);
+        let change = SpotifyStatusChange::between(&curr, &last);
+        assert!(change.track);
+        assert!(!change.volume && !change.playing);
+    }
+
+    #[test]
+    fn same_song_ignores_the_length() {
+        let last = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        let mut json = json::parse(STATUS).unwrap();
+        json["track"]["length"] = 1.into();
+        let curr = SpotifyStatus::from(json.clone());
+        assert_ne!(curr.track_ref(), last.track_ref());
+        assert!(curr.track_ref().same_song_as(last.track_ref()));
+        json["track"]["track_resource"]["uri"] = "spotify:track:1pGZIV8olkbRMjyHWoEXyt".into();
+        let curr = SpotifyStatus::from(json);
+        assert!(!curr.track_ref().same_song_as(last.track_ref()));
+    }
+
+    #[test]
+    fn change_iterates_changed_fields() {
+        let last = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        let mut json = json::parse(STATUS).unwrap();
+        json["volume"] = 0.5.into();
+        json["playing"] = false.into();
+        let curr = SpotifyStatus::from(json);
+        let change = SpotifyStatusChange::between(&curr, &last);
+        assert_eq!(
+            (&change).into_iter().collect::<Vec<_>>(),
+            ["volume", "playing"]
+        );
+        assert_eq!(SpotifyStatusChange::new_true().changed_fields().len(), 14);
+    }
+
+    #[test]
+    fn change_builder_sets_only_the_given_fields() {
+        assert!(SpotifyStatusChange::new_false().changed_fields().is_empty());
+        let change = SpotifyStatusChange::builder()
+            .track(true)
+            .volume(true)
+            .volume(false)
+            .playing(true)
+            .build();
+        assert_eq!(change.changed_fields(), vec!["playing", "track"]);
+    }
+
+    #[test]
+    fn change_merge_accumulates_changes() {
+        let first = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        let mut json = json::parse(STATUS).unwrap();
+        json["volume"] = 0.5.into();
+        let second = SpotifyStatus::from(json.clone());
+        json["playing"] = false.into();
+        let third = SpotifyStatus::from(json);
+        let mut change = SpotifyStatusChange::between(&second, &first);
+        change.merge(&SpotifyStatusChange::between(&third, &second));
+        assert_eq!(change.changed_fields(), ["volume", "playing"]);
+        change.merge(&SpotifyStatusChange::between(&third, &third));
+        assert_eq!(change.changed_fields(), ["volume", "playing"]);
+    }
+
+    #[test]
+    fn change_between_detects_no_change() {
+        let status = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        let change = SpotifyStatusChange::between(&status, &status.clone());
+        assert!(!change.is_user_visible());
+        assert!(!change.server_time && !change.playing_position && !change.track);
+        assert_eq!((&change).into_iter().count(), 0);
+    }
+
+    #[test]
+    fn format_replaces_placeholders() {
+        let status = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        assert_eq!(
+            status
+                .format("{artist} - {track} ({album}) {position}/{length} {volume}% {uri} {other}"),
+            "Rick Astley - Never Gonna Give You Up (Whenever You Need Somebody) \
+             0:42/3:33 75% spotify:track:4uLU6hMCjMI75M1A2tKUQC {other}"
+        );
+    }
+
+    #[test]
+    fn format_does_not_expand_placeholders_in_values() {
+        let mut json = json::parse(STATUS).unwrap();
+        json["track"]["track_resource"]["name"] = "{album}".into();
+        let status = SpotifyStatus::from(json);
+        assert_eq!(status.format("{track} {"), "{album} {");
+    }
+
+    #[test]
+    fn write_to_file_replaces_the_file() {
+        let status = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        let path = env::temp_dir().join(format!("spotify-rs-now-playing-{}.txt", process::id()));
+        fs::write(&path, "previous contents").unwrap();
+        status.write_to_file(&path, "{artist} - {track}").unwrap();
+        let contents = fs::read_to_string(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(contents.unwrap(), "Rick Astley - Never Gonna Give You Up");
+    }
+
+    #[test]
+    fn status_round_trips_through_json() {
+        let status = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        let json = JsonValue::from(&status);
+        assert_eq!(
+            json["track"]["track_resource"]["name"],
+            "Never Gonna Give You Up"
+        );
+        assert_eq!(
+            SpotifyStatus::from(json::parse(&json.dump()).unwrap()),
+            status
+        );
+    }
+
+    #[test]
+    fn numbers_are_parsed_from_strings() {
+        let numeric = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        let mut json = json::parse(STATUS).unwrap();
+        for key in &["volume", "playing_position", "version", "server_time"] {
+            json[*key] = json[*key].dump().into();
+        }
+        json["track"]["length"] = json["track"]["length"].dump().into();
+        assert!(json["volume"].is_string());
+        let stringified = SpotifyStatus::from(json.clone());
+        assert_eq!(stringified.volume(), numeric.volume());
+        assert_eq!(stringified.playing_position(), numeric.playing_position());
+        assert_eq!(stringified.version, numeric.version);
+        assert_ne!(stringified.version, 0);
+        assert_eq!(stringified.timestamp(), numeric.timestamp());
+        assert_eq!(stringified.track_ref().length, numeric.track_ref().length);
+        json["volume"] = "loud".into();
+        assert_eq!(SpotifyStatus::from(json).volume(), 0_f32);
+    }
+
+    #[test]
+    fn server_time_checked_rejects_out_of_range_values() {
+        let mut status = SpotifyStatus::from(json::parse(STATUS).unwrap());
+        assert_eq!(
+            status.server_time_checked(),
+            Some(UNIX_EPOCH + Duration::from_secs(status.server_time as u64))
+        );
+        for server_time in &[-1, i64::MIN] {
+            status.server_time = *server_time;
+            assert_eq!(status.server_time_checked(), None);
+            assert_eq!(status.time_utc().tm_year, 70);
+        }
+        status.server_time = i64::MAX;
+        assert_eq!(status.server_time_checked(), None);
+        assert_eq!(status.time_utc().tm_year, 70);
+    }
+
+    #[test]
+    fn elapsed_since_server_time_saturates_at_zero() {
+        let mut json = json::parse(STATUS).unwrap();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        json["server_time"] = (now - 10).into();
+        let elapsed = SpotifyStatus::from(json.clone()).elapsed_since_server_time();
+        assert!(elapsed >= Duration::from_secs(10) && elapsed < Duration::from_secs(60));
+        json["server_time"] = (now + 1000).into();
+        assert_eq!(
+            SpotifyStatus::from(json).elapsed_since_server_time(),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn estimated_position_advances_while_playing() {
+        let mut json = json::parse(STATUS).unwrap();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        json["server_time"] = (now - 10).into();
+        let status = SpotifyStatus::from(json.clone());
+        assert!(status.estimated_position() >= 52.5);
+        json["server_time"] = (now - 1000).into();
+        assert_eq!(
+            SpotifyStatus::from(json.clone()).estimated_position(),
+            213.0
+        );
+        json["playing"] = false.into();
+        assert_eq!(SpotifyStatus::from(json).estimated_position(), 42.5);
+    }
+
+    #[test]
+    fn volume_as_fraction() {
+        for (volume, expected) in [("0", 0.0), ("0.25", 0.25), ("0.75", 0.75), ("1.0", 1.0)].iter()
+        {
+            let json = json::parse(&format!(r#"{{"volume": {}}}"#, volume)).unwrap();
+            assert_eq!(SpotifyStatus::from(json).volume(), *expected);
+        }
+    }
+
+    #[test]
+    fn volume_as_percentage() {
+        for (volume, expected) in [("25", 0.25), ("64", 0.64), ("100", 1.0), ("250", 1.0)].iter() {
+            let json = json::parse(&format!(r#"{{"volume": {}}}"#, volume)).unwrap();
+            let status = SpotifyStatus::from(json);
+            assert_eq!(status.volume(), *expected);
+            assert!(status.volume_percentage() <= 100_f32);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn from_arbitrary_json_is_well_formed(json in arb_json()) {
+            assert_well_formed(&SpotifyStatus::from(json));
+        }
+
+        #[test]
+        fn from_partial_json_is_well_formed(
+            replacements in prop::collection::vec(
+                (prop::sample::select(vec![
+                    "volume", "playing_position", "server_time", "track",
+                    "client_version", "context", "open_graph_state", "version",
+                ]), arb_json()),
+                0..8,
+            ),
+            removals in prop::collection::vec("[a-z_]{1,16}", 0..4),
+        ) {
+            let mut json = json::parse(STATUS).unwrap();
+            for (key, value) in replacements {
+                json[key] = value;
+            }
+            for key in removals {
+                json.remove(&key);
+            }
+            assert_well_formed(&SpotifyStatus::from(json));
         }
     }
 }
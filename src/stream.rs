@@ -0,0 +1,34 @@
+//! The stream module.
+//!
+//! This module exposes the polled Spotify status as a `futures::Stream`,
+//! so async applications can consume status updates without bridging
+//! the polling thread themselves.
+
+use crate::status::{SpotifyStatus, SpotifyStatusChange};
+use crate::{PollOptions, Result, Spotify};
+use futures::channel::mpsc;
+use futures::Stream;
+use std::time::Duration;
+
+/// Implements `Spotify`.
+impl Spotify {
+    /// Moves `self` to a new thread and begins polling the client
+    /// status in the specified interval. Returns a stream yielding the
+    /// updated status, together with information of which fields had
+    /// changed since the last update.
+    ///
+    /// Polling stops with the first update after the stream is dropped.
+    ///
+    /// Only available with the `async` feature enabled.
+    pub fn status_stream(
+        self,
+        interval: Duration,
+    ) -> Result<impl Stream<Item = (SpotifyStatus, SpotifyStatusChange)>> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.poll_with(
+            PollOptions::new().interval(interval),
+            move |_, status, change| sender.unbounded_send((status, change)).is_ok(),
+        )?;
+        Ok(receiver)
+    }
+}
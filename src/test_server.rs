@@ -0,0 +1,182 @@
+//! The test server module.
+//!
+//! This module contains a tiny HTTP server serving canned
+//! responses, used to test the connector without a Spotify client.
+
+use crate::connector::ConnectorConfig;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+/// A local HTTP server serving canned JSON responses.
+pub struct TestServer {
+    /// The port the server listens on.
+    port: u16,
+    /// The routes served.
+    routes: Arc<Mutex<Vec<(&'static str, String)>>>,
+    /// The request targets received so far.
+    requests: Arc<Mutex<Vec<String>>>,
+    /// The paths whose requests are dropped without a response.
+    hung_up: Arc<Mutex<Vec<&'static str>>>,
+}
+
+/// Gets a configuration connecting to the specified server,
+/// including the OAuth token.
+pub fn config_for(server: &TestServer) -> ConnectorConfig {
+    ConnectorConfig {
+        host: Some("127.0.0.1".to_owned()),
+        port: Some(server.port()),
+        token_url: Some(format!("{}/token", server.url())),
+        ..ConnectorConfig::default()
+    }
+}
+
+/// Returns canned responses for a working Spotify client.
+pub fn spotify_routes() -> Vec<(&'static str, String)> {
+    vec![
+        ("/token", r#"{"t": "oauth-token"}"#.to_owned()),
+        (
+            "/simplecsrf/token.json",
+            r#"{"token": "csrf-token"}"#.to_owned(),
+        ),
+        ("/remote/open.json", r#"{"running": true}"#.to_owned()),
+        ("/remote/play.json", STATUS.to_owned()),
+        ("/remote/pause.json", STATUS.to_owned()),
+        ("/remote/status.json", STATUS.to_owned()),
+    ]
+}
+
+/// A canned status response.
+pub const STATUS: &str = r#"{
+    "version": 9,
+    "client_version": "1.0.42.151.g19de0aa6",
+    "playing": true,
+    "shuffle": false,
+    "repeat": false,
+    "play_enabled": true,
+    "prev_enabled": true,
+    "next_enabled": true,
+    "track": {
+        "track_resource": {
+            "name": "Never Gonna Give You Up",
+            "uri": "spotify:track:4uLU6hMCjMI75M1A2tKUQC",
+            "location": {"og": "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC"}
+        },
+        "artist_resource": {
+            "name": "Rick Astley",
+            "uri": "spotify:artist:0gxyHStUsqpMadRV0Di1Qt",
+            "location": {"og": "https://open.spotify.com/artist/0gxyHStUsqpMadRV0Di1Qt"}
+        },
+        "album_resource": {
+            "name": "Whenever You Need Somebody",
+            "uri": "spotify:album:6N9PS4QXF1D0OWPk0Sxtb4",
+            "location": {"og": "https://open.spotify.com/album/6N9PS4QXF1D0OWPk0Sxtb4"}
+        },
+        "length": 213,
+        "track_type": "normal"
+    },
+    "context": {},
+    "playing_position": 42.5,
+    "server_time": 1500000000,
+    "volume": 0.75,
+    "online": true,
+    "open_graph_state": {"private_session": false, "posting_disabled": true},
+    "running": true
+}"#;
+
+/// Implements `TestServer`.
+impl TestServer {
+    /// Starts a new `TestServer` serving the specified routes.
+    /// Each route maps a request path to a response body.
+    /// Unknown paths are answered with `404 Not Found`.
+    pub fn start(routes: Vec<(&'static str, String)>) -> TestServer {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let routes = Arc::new(Mutex::new(routes));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let hung_up = Arc::new(Mutex::new(Vec::new()));
+        let (served, log, dropped) = (routes.clone(), requests.clone(), hung_up.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                serve(stream, &served, &log, &dropped);
+            }
+        });
+        TestServer {
+            port,
+            routes,
+            requests,
+            hung_up,
+        }
+    }
+    /// Serves the specified body for the specified path from now on,
+    /// replacing the previous body of the route, if any.
+    /// Requests to the path are answered again after `hang_up`.
+    pub fn set_route(&self, path: &'static str, body: String) {
+        self.hung_up
+            .lock()
+            .unwrap()
+            .retain(|&hung_up| hung_up != path);
+        let mut routes = self.routes.lock().unwrap();
+        routes.retain(|route| route.0 != path);
+        routes.push((path, body));
+    }
+    /// Drops the connection of requests to the specified path
+    /// without a response, like a client that stopped responding,
+    /// until the route is set again.
+    pub fn hang_up(&self, path: &'static str) {
+        self.hung_up.lock().unwrap().push(path);
+    }
+    /// Holds back all responses until the returned guard is dropped.
+    pub fn hold(&self) -> MutexGuard<'_, Vec<(&'static str, String)>> {
+        self.routes.lock().unwrap()
+    }
+    /// Gets the port the server listens on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+    /// Gets the base url of the server.
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+    /// Gets the request targets received so far.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Answers a single request.
+fn serve(
+    mut stream: TcpStream,
+    routes: &Mutex<Vec<(&'static str, String)>>,
+    log: &Mutex<Vec<String>>,
+    hung_up: &Mutex<Vec<&'static str>>,
+) {
+    let mut head = Vec::new();
+    let mut buf = [0_u8; 1024];
+    while !head.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => head.extend_from_slice(&buf[..n]),
+        }
+    }
+    let head = String::from_utf8_lossy(&head);
+    let target = head.split_whitespace().nth(1).unwrap_or("/").to_owned();
+    let path = target.split('?').next().unwrap_or("/");
+    let path = path.trim_end_matches('/').to_owned();
+    log.lock().unwrap().push(target);
+    if hung_up.lock().unwrap().contains(&path.as_str()) {
+        return;
+    }
+    let (status, body) = match routes.lock().unwrap().iter().find(|route| route.0 == path) {
+        Some(route) => ("200 OK", route.1.clone()),
+        None => ("404 Not Found", r#"{"error": "not found"}"#.to_owned()),
+    };
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+}
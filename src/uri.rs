@@ -0,0 +1,151 @@
+//! The URI module.
+//!
+//! This module contains the `TrackUri` type, which carries
+//! a validated and normalized Spotify track URI.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// A validated Spotify track URI, such as
+/// `spotify:track:1pGZIV8olkbRMjyHWoEXyt`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TrackUri {
+    /// The normalized uri.
+    uri: String,
+}
+
+/// The error returned when parsing an invalid `TrackUri`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseTrackUriError {
+    /// The rejected input.
+    input: String,
+}
+
+/// Fixes broken (but recoverable) Spotify URIs.
+pub(crate) fn normalize_uri(uri: &str) -> String {
+    // In: https://open.spotify.com/track/1pGZIV8olkbRMjyHWoEXyt
+    // In: open.spotify.com/track/1pGZIV8olkbRMjyHWoEXyt
+    // In: track/1pGZIV8olkbRMjyHWoEXyt
+    // In: track:1pGZIV8olkbRMjyHWoEXyt
+    // Out: spotify:track:1pGZIV8olkbRMjyHWoEXyt
+    let uri = uri
+        .replace("https://", "http://") // https -> http
+        .trim_start_matches("http://") // get rid of protocol
+        .trim_start_matches("open.spotify.com") // get rid of domain name
+        .replace('/', ":") // turn all / into :
+        .trim_start_matches(':') // get rid of : at the beginning
+        .to_owned();
+    if uri.starts_with("spotify:") {
+        uri
+    } else {
+        format!("spotify:{}", uri) // prepend proper protocol
+    }
+}
+
+/// Implements `TrackUri`.
+impl TrackUri {
+    /// Gets the uri as string slice.
+    pub fn as_str(&self) -> &str {
+        &self.uri
+    }
+    /// Gets the track id.
+    pub fn id(&self) -> &str {
+        self.uri.trim_start_matches("spotify:track:")
+    }
+}
+
+/// Implements `FromStr` for `TrackUri`.
+///
+/// Accepts everything `Spotify::play` used to fix up, such as web
+/// links and URIs without the `spotify:` prefix, but rejects
+/// input that doesn't end up as `spotify:track:<id>`.
+impl FromStr for TrackUri {
+    type Err = ParseTrackUriError;
+    fn from_str(s: &str) -> Result<TrackUri, ParseTrackUriError> {
+        let uri = normalize_uri(s.trim());
+        let valid = match uri.strip_prefix("spotify:track:") {
+            Some(id) => !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()),
+            None => false,
+        };
+        if valid {
+            Ok(TrackUri { uri })
+        } else {
+            Err(ParseTrackUriError {
+                input: s.to_owned(),
+            })
+        }
+    }
+}
+
+/// Implements `TryFrom<&'a str>` for `TrackUri`.
+impl<'a> TryFrom<&'a str> for TrackUri {
+    type Error = ParseTrackUriError;
+    fn try_from(s: &'a str) -> Result<TrackUri, ParseTrackUriError> {
+        s.parse()
+    }
+}
+
+/// Implements `TryFrom<String>` for `TrackUri`.
+impl TryFrom<String> for TrackUri {
+    type Error = ParseTrackUriError;
+    fn try_from(s: String) -> Result<TrackUri, ParseTrackUriError> {
+        s.parse()
+    }
+}
+
+/// Implements `fmt::Display` for `TrackUri`.
+impl fmt::Display for TrackUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.uri)
+    }
+}
+
+/// Implements `fmt::Display` for `ParseTrackUriError`.
+impl fmt::Display for ParseTrackUriError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid Spotify track uri: {:?}", self.input)
+    }
+}
+
+/// Implements `Error` for `ParseTrackUriError`.
+impl Error for ParseTrackUriError {}
+
+#[cfg(test)]
+mod tests {
+    use super::TrackUri;
+
+    const URI: &str = "spotify:track:1pGZIV8olkbRMjyHWoEXyt";
+
+    #[test]
+    fn parse_accepted_forms() {
+        for input in &[
+            "spotify:track:1pGZIV8olkbRMjyHWoEXyt",
+            "https://open.spotify.com/track/1pGZIV8olkbRMjyHWoEXyt",
+            "http://open.spotify.com/track/1pGZIV8olkbRMjyHWoEXyt",
+            "open.spotify.com/track/1pGZIV8olkbRMjyHWoEXyt",
+            "track/1pGZIV8olkbRMjyHWoEXyt",
+            "track:1pGZIV8olkbRMjyHWoEXyt",
+            " spotify:track:1pGZIV8olkbRMjyHWoEXyt\n",
+        ] {
+            let uri: TrackUri = input.parse().unwrap();
+            assert_eq!(uri.as_str(), URI);
+            assert_eq!(uri.id(), "1pGZIV8olkbRMjyHWoEXyt");
+        }
+    }
+
+    #[test]
+    fn parse_rejected_forms() {
+        for input in &[
+            "",
+            "spotify:",
+            "spotify:track:",
+            "spotify:album:1pGZIV8olkbRMjyHWoEXyt",
+            "https://example.com/track/1pGZIV8olkbRMjyHWoEXyt",
+            "spotify:track:not a valid id",
+        ] {
+            assert!(input.parse::<TrackUri>().is_err(), "accepted {:?}", input);
+        }
+    }
+}
@@ -1,56 +1,139 @@
-#![cfg(windows)]
+#![cfg(all(windows, feature = "windows-process"))]
+#![allow(unsafe_code)]
+//! The Windows process module.
+//!
+//! This is the only module containing `unsafe` code. Each FFI call
+//! is wrapped in a safe helper, documenting the invariants it relies on.
 
-use std::cmp::Ordering;
-use std::ffi::{CStr, CString};
+use std::io;
 use std::mem::{size_of, zeroed};
-use winapi::shared::minwindef::{DWORD, FALSE, TRUE};
-use winapi::um::processthreadsapi::OpenProcess;
+use winapi::shared::minwindef::{DWORD, FALSE};
+use winapi::shared::winerror::ERROR_NO_MORE_FILES;
+use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+use winapi::um::processthreadsapi::{OpenProcess, TerminateProcess};
 use winapi::um::tlhelp32::{
     CreateToolhelp32Snapshot, Process32First, Process32Next, PROCESSENTRY32, TH32CS_SNAPPROCESS,
 };
-use winapi::um::winnt::{HANDLE, PROCESS_ALL_ACCESS};
+use winapi::um::winnt::{HANDLE, PROCESS_TERMINATE};
+
+/// An owned Windows handle, which is closed on drop.
+struct OwnedHandle(HANDLE);
+
+/// Implements `OwnedHandle`.
+impl OwnedHandle {
+    /// Takes ownership of the specified handle.
+    /// Fails with the last OS error if the handle is null or invalid.
+    fn new(handle: HANDLE) -> io::Result<OwnedHandle> {
+        if handle.is_null() || handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(OwnedHandle(handle))
+    }
+}
+
+/// Implements `Drop` for `OwnedHandle`.
+impl Drop for OwnedHandle {
+    fn drop(&mut self) {
+        // SAFETY: The handle is valid and owned by `self`,
+        // so it is closed exactly once.
+        unsafe { CloseHandle(self.0) };
+    }
+}
 
 /// The `WindowsProcess` struct.
 #[derive(Clone)]
 pub struct WindowsProcess {
-    /// The process handle.
-    handle: HANDLE,
+    /// The process id.
+    pid: DWORD,
+    /// The executable name.
+    name: String,
 }
 
 /// Implements `WindowsProcess`.
 impl WindowsProcess {
-    /// Constructs a new `WindowsProcess`.
-    fn new(handle: HANDLE) -> WindowsProcess {
-        WindowsProcess { handle: handle }
+    /// Gets the process id.
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+    /// Gets the executable name, e.g. `Spotify.exe`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Terminates the process.
+    /// Returns whether the process was terminated.
+    pub fn terminate(&self) -> bool {
+        // SAFETY: `OpenProcess` has no preconditions,
+        // and the returned handle is checked by `OwnedHandle::new`.
+        let handle = unsafe { OpenProcess(PROCESS_TERMINATE, FALSE, self.pid) };
+        let handle = match OwnedHandle::new(handle) {
+            Ok(handle) => handle,
+            Err(_) => return false,
+        };
+        // SAFETY: The handle is valid and was opened with `PROCESS_TERMINATE`.
+        unsafe { TerminateProcess(handle.0, 0) != FALSE }
     }
     /// Finds the first process with the specified name.
-    pub fn find_by_name(name: &str) -> Option<WindowsProcess> {
-        let processes = WindowsProcess::find_all_by_name(name);
-        match processes.len() {
-            0 => None,
-            _ => Some(processes[0].clone()),
-        }
+    /// Fails if the processes cannot be enumerated.
+    pub fn find_by_name(name: &str) -> io::Result<Option<WindowsProcess>> {
+        let processes = WindowsProcess::find_all_by_name(name)?;
+        Ok(processes.first().cloned())
     }
     /// Finds all processes with the specified name.
-    pub fn find_all_by_name(name: &str) -> Vec<WindowsProcess> {
-        let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) };
-        let dest_path = CString::new(name).unwrap();
-        let mut vec = Vec::<WindowsProcess>::new();
-        let mut entry = unsafe { zeroed::<PROCESSENTRY32>() };
-        entry.dwSize = size_of::<PROCESSENTRY32>() as DWORD;
-        let loop_func = |entry: PROCESSENTRY32, vec: &mut Vec<WindowsProcess>| {
-            let path = unsafe { CString::from(CStr::from_ptr(entry.szExeFile.as_ptr())) };
-            if path.cmp(&dest_path) == Ordering::Equal {
-                let handle = unsafe { OpenProcess(PROCESS_ALL_ACCESS, FALSE, entry.th32ProcessID) };
-                vec.push(WindowsProcess::new(handle));
-            }
-        };
-        if unsafe { Process32First(snapshot, &mut entry) == TRUE } {
-            while {
-                loop_func(entry.clone(), &mut vec);
-                unsafe { Process32Next(snapshot, &mut entry) == TRUE }
-            } {}
-        }
-        vec
+    /// Fails if the processes cannot be enumerated,
+    /// e.g. on locked-down systems.
+    pub fn find_all_by_name(name: &str) -> io::Result<Vec<WindowsProcess>> {
+        WindowsProcess::find_all_by(|exe| exe == name)
+    }
+    /// Finds all processes whose executable name matches the predicate.
+    /// Fails if the processes cannot be enumerated.
+    pub fn find_all_by<P>(predicate: P) -> io::Result<Vec<WindowsProcess>>
+    where
+        P: Fn(&str) -> bool,
+    {
+        Ok(process_entries()?
+            .iter()
+            .map(|entry| WindowsProcess {
+                pid: entry.th32ProcessID,
+                name: String::from_utf8_lossy(&exe_name(entry)).into_owned(),
+            })
+            .filter(|process| predicate(&process.name))
+            .collect())
+    }
+}
+
+/// Takes a snapshot of all running processes.
+fn process_entries() -> io::Result<Vec<PROCESSENTRY32>> {
+    // SAFETY: `CreateToolhelp32Snapshot` has no preconditions,
+    // and the returned handle is checked by `OwnedHandle::new`.
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) };
+    let snapshot = OwnedHandle::new(snapshot)?;
+    // SAFETY: `PROCESSENTRY32` is plain old data,
+    // for which all zeroes is a valid value.
+    let mut entry = unsafe { zeroed::<PROCESSENTRY32>() };
+    entry.dwSize = size_of::<PROCESSENTRY32>() as DWORD;
+    let mut entries = Vec::new();
+    // SAFETY: The snapshot is valid and `dwSize` is set as required.
+    let mut found = unsafe { Process32First(snapshot.0, &mut entry) } != FALSE;
+    while found {
+        entries.push(entry);
+        // SAFETY: The snapshot is valid and `dwSize` is still set.
+        found = unsafe { Process32Next(snapshot.0, &mut entry) } != FALSE;
+    }
+    // Running out of entries is the only expected way to stop.
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(code) if code == ERROR_NO_MORE_FILES as i32 => Ok(entries),
+        _ => Err(error),
     }
 }
+
+/// Gets the executable name of the specified process entry,
+/// up to the terminating nul character.
+fn exe_name(entry: &PROCESSENTRY32) -> Vec<u8> {
+    entry
+        .szExeFile
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect()
+}